@@ -0,0 +1,39 @@
+//! Register and memory accesses, as `Chip8::accesses` logs them while instructions run.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A register or a run of memory bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// The bytes from `first` to `last` inclusive, so a run can cover all 64 KiB
+    Mem {
+        first: u16,
+        last: u16,
+    },
+    V(u8),
+    I,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Target::Mem { first, last } if first == last => write!(f, "{:#05X}", first),
+            Target::Mem { first, last } => write!(f, "{:#05X}-{:#05X}", first, last),
+            Target::V(x) => write!(f, "V{:X}", x),
+            Target::I => write!(f, "I"),
+        }
+    }
+}
+
+/// One read or write made by an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub target: Target,
+}
@@ -0,0 +1,620 @@
+//! Assembler for the mnemonics printed by `Instruction`'s `Display` and by
+//! the disassembler, so a disassembly assembles back to the same ROM.
+//!
+//! ```text
+//! ; comments run to the end of the line
+//! speed = 3              ; constants
+//! include "sprites.asm"  ; paths are relative to the including file
+//!
+//! start:
+//!     LD V0, speed
+//!     LD I, ball
+//!     DRW V0, V1, 2
+//!     JP start
+//!
+//! ball:
+//!     db 0b11000000, 0xC0
+//!     dw 0x1234          ; 16-bit big-endian words
+//! ```
+//!
+//! Numbers are decimal, `0x`/`$` hex or `0b` binary, and can be combined with
+//! labels and constants using `+` and `-`. Output starts at 0x200.
+
+use std::{
+    collections::HashMap,
+    error, fmt, fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::disasm::ORIGIN;
+use crate::instruction::Instruction;
+use crate::symbols::{SourceMap, Symbols};
+
+/// How deep includes can nest, a backstop for cycles canonical paths don't catch
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: PathBuf,
+    /// 1-based, 0 when the error is about the file as a whole
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file.display(), self.message)
+        } else {
+            write!(
+                f,
+                "{}:{}:{}: {}",
+                self.file.display(),
+                self.line,
+                self.column,
+                self.message
+            )
+        }
+    }
+}
+
+impl error::Error for AsmError {}
+
+/// An assembled ROM, the addresses of its labels and the lines of its instructions
+#[derive(Debug, Clone)]
+pub struct Assembly {
+    pub rom: Vec<u8>,
+    pub symbols: Symbols,
+    pub source_map: SourceMap,
+}
+
+/// Assembles the file at `path`
+pub fn assemble_file(path: &Path) -> Result<Assembly, AsmError> {
+    let source = fs::read_to_string(path).map_err(|err| AsmError {
+        file: path.to_path_buf(),
+        line: 0,
+        column: 0,
+        message: err.to_string(),
+    })?;
+    assemble(&source, path)
+}
+
+/// Assembles `source`, `path` names it in errors and anchors relative includes
+pub fn assemble(source: &str, path: &Path) -> Result<Assembly, AsmError> {
+    let mut assembler = Assembler::default();
+    assembler.read(source, path)?;
+    Ok(Assembly {
+        rom: assembler.encode()?,
+        symbols: assembler.symbols,
+        source_map: assembler.source_map,
+    })
+}
+
+/// A word or operand and the column it starts at
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    column: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Location {
+    file: Rc<PathBuf>,
+    line: usize,
+}
+
+impl Location {
+    fn error(&self, column: usize, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.as_ref().clone(),
+            line: self.line,
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Instruction {
+        mnemonic: Token,
+        operands: Vec<Token>,
+    },
+    /// `db` or `dw`, `width` is 1 or 2 bytes per value
+    Data { width: usize, values: Vec<Token> },
+}
+
+#[derive(Debug, Default)]
+struct Assembler {
+    statements: Vec<(Location, Statement)>,
+    labels: HashMap<String, u16>,
+    /// Labels in the order they are defined
+    symbols: Symbols,
+    source_map: SourceMap,
+    constants: HashMap<String, (Location, Token)>,
+    /// Address of the next statement
+    addr: usize,
+    /// Canonical paths of the files being read, to catch include cycles
+    includes: Vec<PathBuf>,
+}
+
+impl Assembler {
+    fn read(&mut self, source: &str, path: &Path) -> Result<(), AsmError> {
+        let file = Rc::new(path.to_path_buf());
+        // The top-level source may not come from a file
+        self.includes
+            .push(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+        for (n, line) in source.lines().enumerate() {
+            let location = Location {
+                file: file.clone(),
+                line: n + 1,
+            };
+            self.read_line(&location, line)?;
+        }
+        self.includes.pop();
+        Ok(())
+    }
+
+    fn read_line(&mut self, location: &Location, line: &str) -> Result<(), AsmError> {
+        let line = strip_comment(line);
+        // Columns are 1-based positions in `line`, every slice below is a suffix of it
+        let column = |rest: &str| line.len() - rest.trim_start().len() + 1;
+        let mut rest = line;
+
+        // Label definitions
+        while let Some((word, after)) = next_word(rest) {
+            let name = match word.strip_suffix(':') {
+                Some(name) => name,
+                None => break,
+            };
+            self.define(location, column(rest), name)?;
+            let addr = (ORIGIN as usize + self.addr) as u16;
+            self.labels.insert(name.to_owned(), addr);
+            self.symbols.insert(addr, name);
+            rest = after;
+        }
+
+        let (word, after) = match next_word(rest) {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+
+        // Constants
+        if let Some(value) = after.trim_start().strip_prefix('=') {
+            self.define(location, column(rest), word)?;
+            let value = Token {
+                text: value.trim().to_owned(),
+                column: column(value),
+            };
+            if value.text.is_empty() {
+                return Err(location.error(value.column, "missing constant value"));
+            }
+            self.constants
+                .insert(word.to_owned(), (location.clone(), value));
+            return Ok(());
+        }
+
+        let mnemonic = Token {
+            text: word.to_ascii_uppercase(),
+            column: column(rest),
+        };
+        let operands = split_operands(after, line.len() - after.len());
+        match mnemonic.text.as_str() {
+            "INCLUDE" => self.include(location, &mnemonic, &operands),
+            "DB" | "DW" => {
+                let width = if mnemonic.text == "DB" { 1 } else { 2 };
+                if operands.is_empty() {
+                    return Err(location.error(mnemonic.column, "expected at least one value"));
+                }
+                let len = width * operands.len();
+                self.push(
+                    location,
+                    len,
+                    Statement::Data {
+                        width,
+                        values: operands,
+                    },
+                )
+            }
+            _ => {
+                let long = mnemonic.text == "LD"
+                    && operands
+                        .get(1)
+                        .is_some_and(|op| matches!(Operand::parse(op), Operand::Long(_)));
+                let len = if long { 4 } else { 2 };
+                self.push(location, len, Statement::Instruction { mnemonic, operands })
+            }
+        }
+    }
+
+    fn push(
+        &mut self,
+        location: &Location,
+        len: usize,
+        statement: Statement,
+    ) -> Result<(), AsmError> {
+        let addr = ORIGIN as usize + self.addr;
+        self.addr += len;
+        if ORIGIN as usize + self.addr > 0x10000 {
+            return Err(location.error(1, "program does not fit in 64K of memory"));
+        }
+        if let Statement::Instruction { .. } = statement {
+            self.source_map
+                .insert(addr as u16, &location.file, location.line);
+        }
+        self.statements.push((location.clone(), statement));
+        Ok(())
+    }
+
+    fn define(&self, location: &Location, column: usize, name: &str) -> Result<(), AsmError> {
+        let valid = name
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+        if !valid {
+            return Err(location.error(column, format!("invalid name '{}'", name)));
+        }
+        if register(name).is_some()
+            || name.eq_ignore_ascii_case("LONG")
+            || KEYWORDS.iter().any(|k| k.eq_ignore_ascii_case(name))
+        {
+            return Err(location.error(column, format!("'{}' is a reserved word", name)));
+        }
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return Err(location.error(column, format!("'{}' is already defined", name)));
+        }
+        Ok(())
+    }
+
+    fn include(
+        &mut self,
+        location: &Location,
+        directive: &Token,
+        operands: &[Token],
+    ) -> Result<(), AsmError> {
+        let name = match operands {
+            [op] if op.text.len() >= 2 && op.text.starts_with('"') && op.text.ends_with('"') => {
+                &op.text[1..op.text.len() - 1]
+            }
+            _ => return Err(location.error(directive.column, "expected include \"file\"")),
+        };
+        let path = location
+            .file
+            .parent()
+            .map_or_else(|| PathBuf::from(name), |dir| dir.join(name));
+        let io_error =
+            |err: std::io::Error| location.error(operands[0].column, format!("{}: {}", name, err));
+        let canonical = fs::canonicalize(&path).map_err(io_error)?;
+        if self.includes.contains(&canonical) {
+            return Err(location.error(operands[0].column, format!("'{}' includes itself", name)));
+        }
+        if self.includes.len() >= MAX_INCLUDE_DEPTH {
+            return Err(location.error(
+                operands[0].column,
+                format!("includes nest more than {} deep", MAX_INCLUDE_DEPTH),
+            ));
+        }
+        let source = fs::read_to_string(&path).map_err(io_error)?;
+        self.read(&source, &path)
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, AsmError> {
+        let mut rom = Vec::with_capacity(self.addr);
+        for (location, statement) in &self.statements {
+            match statement {
+                Statement::Data { width, values } => {
+                    for value in values {
+                        if *width == 1 {
+                            rom.push(self.byte(location, value)?);
+                        } else {
+                            let word = self.number(location, value, 0xFFFF)? as u16;
+                            rom.extend_from_slice(&word.to_be_bytes());
+                        }
+                    }
+                }
+                Statement::Instruction { mnemonic, operands } => {
+                    let (instruction, long) = self.instruction(location, mnemonic, operands)?;
+                    rom.extend_from_slice(&instruction.encode().to_be_bytes());
+                    if let Some(addr) = long {
+                        rom.extend_from_slice(&addr.to_be_bytes());
+                    }
+                }
+            }
+        }
+        Ok(rom)
+    }
+
+    /// Parses one instruction, with the address that follows `LD I, LONG`
+    fn instruction(
+        &self,
+        location: &Location,
+        mnemonic: &Token,
+        operands: &[Token],
+    ) -> Result<(Instruction, Option<u16>), AsmError> {
+        use Instruction::*;
+
+        let ops: Vec<Operand> = operands.iter().map(Operand::parse).collect();
+        let addr = |op: &Token| self.number(location, op, 0xFFF).map(|n| n as u16);
+        let byte = |op: &Token| self.byte(location, op);
+        let nibble = |op: &Token| self.number(location, op, 0xF).map(|n| n as u8);
+        let wrong = || {
+            location.error(
+                mnemonic.column,
+                format!("invalid operands for {}", mnemonic.text),
+            )
+        };
+
+        let instruction = match (mnemonic.text.as_str(), &ops[..]) {
+            ("CLS", []) => Cls,
+            ("RET", []) => Ret,
+            ("SCR", []) => ScrollRight,
+            ("SCL", []) => ScrollLeft,
+            ("EXIT", []) => Exit,
+            ("LOW", []) => Lores,
+            ("HIGH", []) => Hires,
+            ("AUDIO", []) => Audio,
+            ("SCD", [Operand::Value]) => ScrollDown(nibble(&operands[0])?),
+            ("SCU", [Operand::Value]) => ScrollUp(nibble(&operands[0])?),
+            ("PLANE", [Operand::Value]) => Plane(nibble(&operands[0])?),
+            ("SYS", [Operand::Value]) => Sys(addr(&operands[0])?),
+            ("JP", [Operand::Value]) => Jump(addr(&operands[0])?),
+            ("JP", [Operand::V(0), Operand::Value]) => JumpOffset(addr(&operands[1])?),
+            ("CALL", [Operand::Value]) => Call(addr(&operands[0])?),
+            ("SE", [Operand::V(x), Operand::V(y)]) => SkipEqReg(*x, *y),
+            ("SE", [Operand::V(x), Operand::Value]) => SkipEqImm(*x, byte(&operands[1])?),
+            ("SNE", [Operand::V(x), Operand::V(y)]) => SkipNeReg(*x, *y),
+            ("SNE", [Operand::V(x), Operand::Value]) => SkipNeImm(*x, byte(&operands[1])?),
+            ("SAVE", [Operand::V(x), Operand::V(y)]) => SaveRange(*x, *y),
+            ("LOAD", [Operand::V(x), Operand::V(y)]) => LoadRange(*x, *y),
+            ("ADD", [Operand::V(x), Operand::V(y)]) => AddReg(*x, *y),
+            ("ADD", [Operand::V(x), Operand::Value]) => AddImm(*x, byte(&operands[1])?),
+            ("ADD", [Operand::Keyword("I"), Operand::V(x)]) => AddI(*x),
+            ("OR", [Operand::V(x), Operand::V(y)]) => Or(*x, *y),
+            ("AND", [Operand::V(x), Operand::V(y)]) => And(*x, *y),
+            ("XOR", [Operand::V(x), Operand::V(y)]) => Xor(*x, *y),
+            ("SUB", [Operand::V(x), Operand::V(y)]) => Sub(*x, *y),
+            ("SUBN", [Operand::V(x), Operand::V(y)]) => SubReverse(*x, *y),
+            ("SHR", [Operand::V(x), Operand::V(y)]) => ShiftRight(*x, *y),
+            ("SHL", [Operand::V(x), Operand::V(y)]) => ShiftLeft(*x, *y),
+            ("RND", [Operand::V(x), Operand::Value]) => Random(*x, byte(&operands[1])?),
+            ("DRW", [Operand::V(x), Operand::V(y), Operand::Value]) => {
+                Draw(*x, *y, nibble(&operands[2])?)
+            }
+            ("SKP", [Operand::V(x)]) => SkipKey(*x),
+            ("SKNP", [Operand::V(x)]) => SkipNotKey(*x),
+            ("PITCH", [Operand::V(x)]) => Pitch(*x),
+            ("LD", [Operand::Keyword("I"), Operand::Long(target)]) => {
+                let target = Token {
+                    text: target.to_string(),
+                    column: operands[1].column + 5,
+                };
+                let nnnn = self.number(location, &target, 0xFFFF)? as u16;
+                return Ok((LoadILong, Some(nnnn)));
+            }
+            ("LD", [Operand::Keyword("I"), Operand::Value]) => LoadI(addr(&operands[1])?),
+            ("LD", [Operand::V(x), Operand::V(y)]) => Move(*x, *y),
+            ("LD", [Operand::V(x), Operand::Value]) => LoadImm(*x, byte(&operands[1])?),
+            ("LD", [Operand::V(x), Operand::Keyword("DT")]) => GetDelay(*x),
+            ("LD", [Operand::V(x), Operand::Keyword("K")]) => WaitKey(*x),
+            ("LD", [Operand::V(x), Operand::Keyword("[I]")]) => Load(*x),
+            ("LD", [Operand::V(x), Operand::Keyword("R")]) => LoadRpl(*x),
+            ("LD", [Operand::Keyword("DT"), Operand::V(x)]) => SetDelay(*x),
+            ("LD", [Operand::Keyword("ST"), Operand::V(x)]) => SetSound(*x),
+            ("LD", [Operand::Keyword("F"), Operand::V(x)]) => Font(*x),
+            ("LD", [Operand::Keyword("HF"), Operand::V(x)]) => BigFont(*x),
+            ("LD", [Operand::Keyword("B"), Operand::V(x)]) => Bcd(*x),
+            ("LD", [Operand::Keyword("[I]"), Operand::V(x)]) => Store(*x),
+            ("LD", [Operand::Keyword("R"), Operand::V(x)]) => StoreRpl(*x),
+            (name, _) if MNEMONICS.contains(&name) => return Err(wrong()),
+            _ => {
+                return Err(location.error(
+                    mnemonic.column,
+                    format!("unknown instruction '{}'", mnemonic.text),
+                ))
+            }
+        };
+        Ok((instruction, None))
+    }
+
+    fn byte(&self, location: &Location, token: &Token) -> Result<u8, AsmError> {
+        // Negative bytes are written as two's complement, handy for ADD Vx, -1
+        let value = self.eval(location, token, 0)?;
+        if !(-128..=0xFF).contains(&value) {
+            return Err(location.error(token.column, format!("{} does not fit in a byte", value)));
+        }
+        Ok(value as u8)
+    }
+
+    /// Evaluates `token` and checks it is in `0..=max`
+    fn number(&self, location: &Location, token: &Token, max: i64) -> Result<i64, AsmError> {
+        let value = self.eval(location, token, 0)?;
+        if !(0..=max).contains(&value) {
+            return Err(location.error(
+                token.column,
+                format!("{} is out of range, expected 0 to {:#X}", value, max),
+            ));
+        }
+        Ok(value)
+    }
+
+    /// Sums the terms of an expression like `sprites + 5 - 0x1`
+    fn eval(&self, location: &Location, token: &Token, depth: usize) -> Result<i64, AsmError> {
+        let text = &token.text;
+        let mut total = 0i64;
+        let mut sign = 1;
+        let mut start = 0;
+        let mut terms = Vec::new();
+        for (i, c) in text.char_indices() {
+            if c == '+' || (c == '-' && i > 0) {
+                terms.push((sign, start, i));
+                sign = if c == '+' { 1 } else { -1 };
+                start = i + 1;
+            } else if c == '-' && i == 0 {
+                sign = -1;
+                start = 1;
+            }
+        }
+        terms.push((sign, start, text.len()));
+
+        for (sign, start, end) in terms {
+            let term = text[start..end].trim();
+            let column = token.column
+                + start
+                + (text[start..end].len() - text[start..end].trim_start().len());
+            if term.is_empty() {
+                return Err(location.error(column, "expected a number or a name"));
+            }
+            let value = match parse_number(term) {
+                Some(value) => value,
+                None => self.symbol(location, term, column, depth)?,
+            };
+            total = value
+                .checked_mul(sign)
+                .and_then(|value| total.checked_add(value))
+                .ok_or_else(|| location.error(column, "expression out of range"))?;
+        }
+        Ok(total)
+    }
+
+    fn symbol(
+        &self,
+        location: &Location,
+        name: &str,
+        column: usize,
+        depth: usize,
+    ) -> Result<i64, AsmError> {
+        if let Some(&addr) = self.labels.get(name) {
+            return Ok(addr as i64);
+        }
+        match self.constants.get(name) {
+            Some((defined_at, value)) => {
+                if depth > 16 {
+                    return Err(
+                        location.error(column, format!("'{}' is defined in terms of itself", name))
+                    );
+                }
+                self.eval(defined_at, value, depth + 1)
+            }
+            None if name.starts_with(|c: char| c.is_ascii_digit() || c == '$') => {
+                Err(location.error(column, format!("invalid number '{}'", name)))
+            }
+            None => Err(location.error(column, format!("undefined name '{}'", name))),
+        }
+    }
+}
+
+/// Operand shapes the instruction table matches on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand<'a> {
+    V(u8),
+    /// `I`, `[I]`, `DT`, `ST`, `K`, `F`, `HF`, `B` or `R`
+    Keyword(&'static str),
+    /// `LONG <expr>`
+    Long(&'a str),
+    /// A number, name or expression
+    Value,
+}
+
+const KEYWORDS: [&str; 9] = ["I", "[I]", "DT", "ST", "K", "F", "HF", "B", "R"];
+
+const MNEMONICS: &[&str] = &[
+    "CLS", "RET", "SCR", "SCL", "EXIT", "LOW", "HIGH", "AUDIO", "SCD", "SCU", "PLANE", "SYS", "JP",
+    "CALL", "SE", "SNE", "SAVE", "LOAD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL",
+    "RND", "DRW", "SKP", "SKNP", "PITCH", "LD",
+];
+
+impl<'a> Operand<'a> {
+    fn parse(token: &'a Token) -> Self {
+        let text = token.text.as_str();
+        if let Some(x) = register(text) {
+            return Operand::V(x);
+        }
+        if let Some(keyword) = KEYWORDS.iter().find(|k| k.eq_ignore_ascii_case(text)) {
+            return Operand::Keyword(keyword);
+        }
+        if text
+            .get(..5)
+            .is_some_and(|p| p.eq_ignore_ascii_case("LONG "))
+        {
+            return Operand::Long(text[5..].trim());
+        }
+        Operand::Value
+    }
+}
+
+fn register(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V' | 'v'), Some(x), None) => x.to_digit(16).map(|x| x as u8),
+        _ => None,
+    }
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (digits, radix) =
+        if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            (hex, 16)
+        } else if let Some(hex) = text.strip_prefix('$') {
+            (hex, 16)
+        } else if let Some(bin) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+            (bin, 2)
+        } else {
+            (text, 10)
+        };
+    i64::from_str_radix(digits, radix).ok()
+}
+
+/// Cuts a `;` comment off, leaving semicolons in strings alone
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// The first whitespace separated word and what follows it
+fn next_word(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim_start();
+    if text.is_empty() {
+        return None;
+    }
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    Some((&text[..end], &text[end..]))
+}
+
+/// Comma separated operands, `offset` is the column `text` starts after
+fn split_operands(text: &str, offset: usize) -> Vec<Token> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+    let mut operands = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    // Commas inside an include path are part of it
+    let commas = text.char_indices().filter(|&(_, c)| {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        c == ',' && !quoted
+    });
+    for end in commas.map(|(i, _)| i).chain(std::iter::once(text.len())) {
+        let part = &text[start..end];
+        let leading = part.len() - part.trim_start().len();
+        operands.push(Token {
+            text: part.trim().to_owned(),
+            column: offset + start + leading + 1,
+        });
+        start = end + 1;
+    }
+    operands
+}
@@ -0,0 +1,191 @@
+use std::{
+    cell::Cell,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    rc::Rc,
+    str::FromStr,
+};
+
+/// Something that makes the CHIP-8 buzzer audible (or visible).
+///
+/// The buzzer sounds while the sound timer is non-zero, sinks are only told
+/// about the edges.
+pub trait AudioSink {
+    fn buzzer_on(&mut self);
+    fn buzzer_off(&mut self);
+    /// Called once per 60Hz frame, for sinks that render sound over time
+    fn frame(&mut self) {}
+    /// Called once on exit
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Rings the terminal bell when the buzzer turns on.
+pub struct BellSink;
+
+impl AudioSink for BellSink {
+    fn buzzer_on(&mut self) {
+        let mut stdout = io::stdout();
+        // Nothing sensible to do if the terminal is gone
+        let _ = stdout.write_all(b"\x07").and_then(|_| stdout.flush());
+    }
+    fn buzzer_off(&mut self) {}
+}
+
+/// Raises a shared flag while the buzzer is on, the frontend flashes the border with it.
+pub struct VisualSink {
+    flash: Rc<Cell<bool>>,
+}
+
+impl VisualSink {
+    pub fn new(flash: Rc<Cell<bool>>) -> Self {
+        VisualSink { flash }
+    }
+}
+
+impl AudioSink for VisualSink {
+    fn buzzer_on(&mut self) {
+        self.flash.set(true);
+    }
+    fn buzzer_off(&mut self) {
+        self.flash.set(false);
+    }
+}
+
+/// Renders the buzzer as a square wave into a 16-bit mono WAV file.
+pub struct WavSink {
+    path: PathBuf,
+    samples: Vec<i16>,
+    on: bool,
+    phase: u32,
+}
+
+impl WavSink {
+    pub const SAMPLE_RATE: u32 = 44100;
+    /// Frequency of the tone, the COSMAC VIP buzzer was somewhere around here
+    pub const FREQUENCY: u32 = 440;
+    const AMPLITUDE: i16 = i16::MAX / 4;
+
+    pub fn new(path: PathBuf) -> Self {
+        WavSink {
+            path,
+            samples: Vec::new(),
+            on: false,
+            phase: 0,
+        }
+    }
+
+    fn write_wav(&self, out: &mut impl Write) -> io::Result<()> {
+        let data_len = (self.samples.len() * 2) as u32;
+        out.write_all(b"RIFF")?;
+        out.write_all(&(36 + data_len).to_le_bytes())?;
+        out.write_all(b"WAVE")?;
+        out.write_all(b"fmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        // PCM, mono
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&WavSink::SAMPLE_RATE.to_le_bytes())?;
+        // byte rate and block align
+        out.write_all(&(WavSink::SAMPLE_RATE * 2).to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&data_len.to_le_bytes())?;
+        for sample in &self.samples {
+            out.write_all(&sample.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl AudioSink for WavSink {
+    fn buzzer_on(&mut self) {
+        self.on = true;
+    }
+    fn buzzer_off(&mut self) {
+        self.on = false;
+    }
+    fn frame(&mut self) {
+        let half_period = WavSink::SAMPLE_RATE / WavSink::FREQUENCY / 2;
+        for _ in 0..WavSink::SAMPLE_RATE / 60 {
+            let sample = if !self.on {
+                0
+            } else if self.phase < half_period {
+                WavSink::AMPLITUDE
+            } else {
+                -WavSink::AMPLITUDE
+            };
+            self.samples.push(sample);
+            self.phase = (self.phase + 1) % (half_period * 2);
+        }
+    }
+    fn finish(&mut self) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(&self.path)?);
+        self.write_wav(&mut out)?;
+        out.flush()
+    }
+}
+
+/// Silence.
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn buzzer_on(&mut self) {}
+    fn buzzer_off(&mut self) {}
+}
+
+/// Tracks the buzzer state and notifies the sink when it changes.
+pub struct Buzzer {
+    sink: Box<dyn AudioSink>,
+    on: bool,
+}
+
+impl Buzzer {
+    pub fn new(sink: Box<dyn AudioSink>) -> Self {
+        Buzzer { sink, on: false }
+    }
+
+    /// Call once per 60Hz frame with the current buzzer state
+    pub fn update(&mut self, on: bool) {
+        if on != self.on {
+            self.on = on;
+            if on {
+                self.sink.buzzer_on();
+            } else {
+                self.sink.buzzer_off();
+            }
+        }
+        self.sink.frame();
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.sink.finish()
+    }
+}
+
+/// Audio sinks selectable from the command line, the WAV sink has its own flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkKind {
+    Bell,
+    Visual,
+    None,
+}
+
+impl FromStr for SinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bell" => Ok(SinkKind::Bell),
+            "visual" => Ok(SinkKind::Visual),
+            "none" | "off" => Ok(SinkKind::None),
+            _ => Err(format!(
+                "unknown audio sink '{}', expected one of: bell, visual, none",
+                s
+            )),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    net::TcpListener,
+    path::Path,
+};
+
+use chipterm::{asm, dap, disasm};
+
+use crate::Command;
+
+pub fn run(command: Command) -> Result<(), io::Error> {
+    match command {
+        Command::Disasm {
+            rompath,
+            platform,
+            output,
+            no_addresses,
+        } => {
+            let rom = fs::read(&rompath)?;
+            let mut disassembly = disasm::disassemble(&rom, platform);
+            if no_addresses {
+                disassembly = disassembly.without_addresses();
+            }
+            let listing = disassembly.to_string();
+            write_output(output.as_deref(), listing.as_bytes())
+        }
+        Command::Asm {
+            source,
+            output,
+            symbols,
+            source_map,
+        } => {
+            let assembly = match asm::assemble_file(&source) {
+                Ok(assembly) => assembly,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            };
+            let output = output.unwrap_or_else(|| source.with_extension("ch8"));
+            write_output(Some(&output), &assembly.rom)?;
+            if let Some(path) = symbols {
+                let mut out = BufWriter::new(File::create(path)?);
+                assembly.symbols.write(&mut out)?;
+                out.flush()?;
+            }
+            if let Some(path) = source_map {
+                // Absolute paths, so editors find the sources from anywhere
+                let mut map = assembly.source_map;
+                map.resolve_paths(&std::env::current_dir()?);
+                let mut out = BufWriter::new(File::create(path)?);
+                map.write(&mut out)?;
+                out.flush()?;
+            }
+            Ok(())
+        }
+        Command::Dap { port: None } => dap::serve(io::stdin(), io::stdout()),
+        Command::Dap { port: Some(port) } => {
+            let listener = TcpListener::bind(("127.0.0.1", port))?;
+            eprintln!("waiting for a debug adapter client on 127.0.0.1:{}", port);
+            for stream in listener.incoming() {
+                let stream = stream?;
+                if let Err(err) = dap::serve(stream.try_clone()?, stream) {
+                    eprintln!("session ended: {}", err);
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Writes to `path`, or to stdout without one
+fn write_output(path: Option<&Path>, data: &[u8]) -> Result<(), io::Error> {
+    match path {
+        Some(path) => {
+            let mut out = BufWriter::new(File::create(path)?);
+            out.write_all(data)?;
+            out.flush()
+        }
+        None => io::stdout().write_all(data),
+    }
+}
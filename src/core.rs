@@ -1,14 +1,101 @@
-use std::io;
+use std::{error, fmt, io, ops::Range};
 
-use rand::{thread_rng, Rng};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 
+use crate::access::{Access, AccessKind, Target};
 use crate::display::Display;
+use crate::instruction::Instruction;
+use crate::platform::Platform;
+use crate::quirks::{LoadStore, Quirks};
+
+/// Why `Chip8::emulation_cycle` could not execute an instruction.
+///
+/// The program counter is left on the faulting instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecError {
+    /// 2NNN with all 16 stack entries in use
+    StackOverflow {
+        pc: u16,
+    },
+    /// 00EE with an empty stack
+    StackUnderflow {
+        pc: u16,
+    },
+    /// An instruction fetch or an access through I past the end of memory
+    MemoryOutOfBounds {
+        addr: usize,
+    },
+    UnknownOpcode {
+        pc: u16,
+        opcode: u16,
+    },
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecError::StackOverflow { pc } => {
+                write!(
+                    f,
+                    "stack overflow at {:#05X}, more than 16 nested calls",
+                    pc
+                )
+            }
+            ExecError::StackUnderflow { pc } => {
+                write!(
+                    f,
+                    "stack underflow at {:#05X}, return outside a subroutine",
+                    pc
+                )
+            }
+            ExecError::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds at {:#X}", addr)
+            }
+            ExecError::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode {:#06X} at {:#05X}", opcode, pc)
+            }
+        }
+    }
+}
+
+impl error::Error for ExecError {}
+
+/// Ranges written to more often than this between two `take_dirty` calls
+/// count as all of memory
+const MAX_DIRTY_RANGES: usize = 32;
+
+/// What changed since the last `Chip8::take_dirty`, so `Rewind` only compares that.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Dirty {
+    /// Memory ranges written, `None` when any byte may have changed
+    pub(crate) mem: Option<Vec<Range<usize>>>,
+    pub(crate) gfx: bool,
+}
+
+impl Dirty {
+    fn clean() -> Self {
+        Dirty {
+            mem: Some(Vec::new()),
+            gfx: false,
+        }
+    }
+
+    fn all() -> Self {
+        Dirty {
+            mem: None,
+            gfx: true,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Chip8 {
     pub opcode: u16,
     pub program_counter: u16,
-    pub mem: [u8; 4096],
+    pub platform: Platform,
+    /// Call `mark_dirty` after writing here from outside the core, or rewinding misses the change
+    pub mem: Vec<u8>,
     pub vreg: [u8; 16],
     pub ireg: u16,
     pub gfx: Display,
@@ -17,34 +104,86 @@ pub struct Chip8 {
     pub stack: [u16; 16],
     pub stack_pointer: u16,
     pub keys: [u8; 16],
-    pub desc: String,
+    pub quirks: Quirks,
+    /// SUPER-CHIP RPL user flags, saved and restored by FX75/FX85
+    pub rpl: [u8; 16],
+    /// XO-CHIP 1-bit audio pattern loaded by F002
+    pub audio_pattern: [u8; 16],
+    /// XO-CHIP playback rate of the audio pattern set by FX3A, 64 is 4000Hz
+    pub pitch: u8,
+    /// Set by the SUPER-CHIP 00FD exit instruction, no more cycles are executed
+    pub exited: bool,
+    /// Source of CXNN random numbers, part of the state so rewinding replays the same values
+    pub rng: ChaCha8Rng,
+    /// Seed `rng` was created from, save states rebuild the generator with it
+    pub seed: u64,
+    /// Set on every 60Hz tick, consumed by DXYN when `quirks.display_wait` is on.
+    pub vblank: bool,
+    dirty: Dirty,
+    /// Register and memory accesses of the last instruction, while logging is on
+    access_log: Option<AccessLog>,
+}
+
+/// What the last instruction accessed
+#[derive(Debug, Clone, Default)]
+struct AccessLog {
+    accesses: Vec<Access>,
+    /// The bytes the memory writes in `accesses` replaced, in the same order
+    overwritten: Vec<u8>,
+}
+
+impl AccessLog {
+    fn clear(&mut self) {
+        self.accesses.clear();
+        self.overwritten.clear();
+    }
 }
 
 impl Chip8 {
-    pub fn new() -> Self {
+    pub fn new(platform: Platform, quirks: Quirks, seed: u64) -> Self {
         let mut new = Chip8 {
             opcode: 0,
             program_counter: 512,
-            mem: [0; 4096],
+            platform,
+            mem: vec![0; platform.mem_size()],
             vreg: [0; 16],
             ireg: 0,
-            gfx: Display {
-                grid: [[0u8; 32]; 64],
-            },
+            gfx: Display::new(),
             delay_timer: 0,
             sound_timer: 0,
             stack: [0; 16],
             stack_pointer: 0,
             keys: [0; 16],
-            desc: String::from(""),
+            quirks,
+            rpl: [0; 16],
+            audio_pattern: [0; 16],
+            pitch: 64,
+            exited: false,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            seed,
+            vblank: false,
+            dirty: Dirty::all(),
+            access_log: None,
         };
         new.load_fonts();
         new
     }
 
+    /// Presses `key` (0x0-0xF) and releases every other key.
+    ///
+    /// Terminals don't report key releases, so a press stays down until
+    /// `decay_keys` has been called twice or `release_key` is called.
     pub fn press_key(&mut self, key: u8) {
         self.keys.fill(0);
-        self.keys[key as usize] = 2;
+        self.keys[key as usize & 0xF] = 2;
+    }
+    pub fn release_key(&mut self, key: u8) {
+        self.keys[key as usize & 0xF] = 0;
+    }
+    pub fn decay_keys(&mut self) {
+        for key in self.keys.iter_mut() {
+            *key = key.saturating_sub(1);
+        }
     }
 
     pub fn load_fonts(&mut self) {
@@ -70,260 +209,555 @@ impl Chip8 {
         for i in 0x050..=0x09F {
             self.mem[i] = *fonts.next().unwrap();
         }
+        // SUPER-CHIP 8x10 digits, right after the small font
+        let mut big_fonts = [
+            0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+            0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+            0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+            0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+            0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+            0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+            0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+            0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+            0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+        ]
+        .iter();
+        for i in 0x0A0..=0x103 {
+            self.mem[i] = *big_fonts.next().unwrap();
+        }
     }
-    pub fn load_game(&mut self, rom: &Vec<u8>) -> Result<(), io::Error> {
-        let mut memptr = 0x200;
-        // let len = romdata.len() + 0x200;
-        for data in rom {
-            self.mem[memptr] = *data;
-            memptr += 1;
+    /// Copies `rom` to 0x200, failing if it runs past the end of memory
+    pub fn load_game(&mut self, rom: &[u8]) -> Result<(), io::Error> {
+        let room = self.mem.len() - 0x200;
+        if rom.len() > room {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "ROM is {} bytes, only {} fit in {} memory",
+                    rom.len(),
+                    room,
+                    self.platform
+                ),
+            ));
         }
+        self.mem[0x200..0x200 + rom.len()].copy_from_slice(rom);
+        self.mark_dirty(0x200..0x200 + rom.len());
         Ok(())
     }
-    pub fn decrement_delay_timer(&mut self) {
+    /// Counts both timers down, call at 60Hz
+    pub fn tick_timers(&mut self) {
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+    }
+    /// Closes a 60Hz frame: counts the timers down and signals the vertical blank
+    pub fn end_frame(&mut self) {
+        self.tick_timers();
+        self.vertical_blank();
     }
-    pub fn emulation_cycle(&mut self) {
+    /// The buzzer sounds for as long as the sound timer is non-zero
+    pub fn buzzer_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+    pub fn vertical_blank(&mut self) {
+        self.vblank = true;
+    }
+    /// Notes a write to `mem` made from outside the core, so rewinding sees it
+    pub fn mark_dirty(&mut self, range: Range<usize>) {
+        let end = range.end.min(self.mem.len());
+        let range = range.start.min(end)..end;
+        if let Some(ranges) = &mut self.dirty.mem {
+            if ranges.len() < MAX_DIRTY_RANGES {
+                ranges.push(range);
+            } else {
+                self.dirty.mem = None;
+            }
+        }
+    }
+    /// Notes that memory and the display may have changed anywhere
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty = Dirty::all();
+    }
+    /// Starts or stops logging the register and memory accesses each instruction makes
+    pub fn log_accesses(&mut self, on: bool) {
+        match (on, &self.access_log) {
+            (true, None) => self.access_log = Some(AccessLog::default()),
+            (false, _) => self.access_log = None,
+            _ => {}
+        }
+    }
+    pub fn logs_accesses(&self) -> bool {
+        self.access_log.is_some()
+    }
+    /// The register and memory accesses the last instruction made, in the
+    /// order it made them. Empty unless `log_accesses` is on, and after an
+    /// error. Instruction fetches and stack pushes are not included.
+    pub fn accesses(&self) -> &[Access] {
+        self.access_log
+            .as_ref()
+            .map_or(&[], |log| log.accesses.as_slice())
+    }
+    /// The byte at `addr` before the last instruction ran. Bytes it wrote are
+    /// only known while `log_accesses` is on, otherwise this is the current byte.
+    pub fn mem_before(&self, addr: u16) -> Option<u8> {
+        if let Some(log) = &self.access_log {
+            let mut offset = 0;
+            for access in &log.accesses {
+                if let (AccessKind::Write, Target::Mem { first, last }) =
+                    (access.kind, access.target)
+                {
+                    if (first..=last).contains(&addr) {
+                        return log
+                            .overwritten
+                            .get(offset + (addr - first) as usize)
+                            .copied();
+                    }
+                    offset += (last - first) as usize + 1;
+                }
+            }
+        }
+        self.mem.get(addr as usize).copied()
+    }
+    /// What changed since the last call
+    pub(crate) fn take_dirty(&mut self) -> Dirty {
+        std::mem::replace(&mut self.dirty, Dirty::clean())
+    }
+    pub fn emulation_cycle(&mut self) -> Result<(), ExecError> {
+        if self.exited {
+            return Ok(());
+        }
+        let pc = self.program_counter;
+        if let Some(log) = &mut self.access_log {
+            log.clear();
+        }
+        self.execute().inspect_err(|_| {
+            self.program_counter = pc;
+            if let Some(log) = &mut self.access_log {
+                log.clear();
+            }
+        })?;
+        Ok(())
+    }
+    fn execute(&mut self) -> Result<(), ExecError> {
+        use Instruction::*;
+
+        let pc = self.program_counter;
         // Fetch opcode from memory
-        let opcode = (self.mem[self.program_counter as usize] as u16) << 8
-            | self.mem[(self.program_counter + 1) as usize] as u16;
+        let fetch = self.mem_range(pc as usize, 2)?;
+        let opcode = (self.mem[fetch.start] as u16) << 8 | self.mem[fetch.start + 1] as u16;
         self.opcode = opcode;
         // Increment program counter
-        self.program_counter += 2;
+        self.program_counter = self.program_counter.wrapping_add(2);
 
         // Decode opcode
+        let unknown = ExecError::UnknownOpcode { pc, opcode };
+        let instruction = Instruction::decode(opcode).ok_or(unknown)?;
+        if instruction.is_xochip() && self.platform != Platform::XoChip {
+            return Err(unknown);
+        }
+        if matches!(
+            instruction,
+            Cls | ScrollDown(_) | ScrollUp(_) | ScrollRight | ScrollLeft | Lores | Hires | Draw(..)
+        ) {
+            self.dirty.gfx = true;
+        }
 
-        // First "nibble" aka instruction
-        let instruction = ((opcode & 0xF000) >> 12) as u8;
-
-        // Second "nibble"
-        let x = ((opcode & 0x0F00) >> 8) as u8;
-
-        // Third "nibble"
-        let y = ((opcode & 0x00F0) >> 4) as u8;
-
-        // Fourth "nibble" and constants
-        let n = (opcode & 0x000F) as u8;
-        let nn = (opcode & 0x00FF) as u8;
-        let nnn = opcode & 0x0FFF;
         match instruction {
-            0 => match nn {
-                //00EE	Flow	return;	    Returns from a subroutine.
-                0xEE => {
-                    self.desc = format!(
-                        "[{:#X}] opcode\nFlow return. \
-                        Returns from subroutine. Decrement stack size from {} to {}. \
-                        Set program counter to previous stack value",
-                        instruction,
-                        self.stack_pointer,
-                        self.stack_pointer - 1
-                    );
-                    self.stack_pointer -= 1;
-                    self.program_counter = self.stack[self.stack_pointer as usize];
-                }
-                //00E0	Display	clear()	    Clears the screen.
-                0xE0 => {
-                    self.gfx.cls();
-                    self.desc = format!(
-                        "[{:#X}] opcode\nClear display. Fills screen with 0's.",
-                        instruction
-                    )
-                }
-                //0NNN	Call	            Calls machine code routine (RCA 1802 for COSMAC VIP) at address NNN. Not necessary for most ROMs.
-                _ => {
-                    self.stack[self.stack_pointer as usize] = self.program_counter;
-                    self.stack_pointer += 1;
-                    self.program_counter = nnn;
+            //00CN	Display	scroll_down(N)	Scrolls the display down by N pixels. (SUPER-CHIP)
+            ScrollDown(n) => self.gfx.scroll_down(n as usize),
+            //00DN	Display	scroll_up(N)	Scrolls the selected planes up by N pixels. (XO-CHIP)
+            ScrollUp(n) => self.gfx.scroll_up(n as usize),
+            //00FB	Display	scroll_right()	Scrolls the display right by 4 pixels. (SUPER-CHIP)
+            ScrollRight => self.gfx.scroll_right(4),
+            //00FC	Display	scroll_left()	Scrolls the display left by 4 pixels. (SUPER-CHIP)
+            ScrollLeft => self.gfx.scroll_left(4),
+            //00FD	Flow	exit()	    Exits the interpreter. (SUPER-CHIP)
+            Exit => self.exited = true,
+            //00FE	Display	lores()	    Switches to 64x32 low resolution mode. (SUPER-CHIP)
+            Lores => self.gfx.set_hires(false),
+            //00FF	Display	hires()	    Switches to 128x64 high resolution mode. (SUPER-CHIP)
+            Hires => self.gfx.set_hires(true),
+            //00EE	Flow	return;	    Returns from a subroutine.
+            Ret => {
+                if self.stack_pointer == 0 {
+                    return Err(ExecError::StackUnderflow { pc });
                 }
-            },
-            //1NNN	Flow	goto NNN;	Jumps to address NNN.
-            1 => {
-                self.program_counter = nnn;
+                self.stack_pointer -= 1;
+                self.program_counter = self.stack[self.stack_pointer as usize];
             }
+            //00E0	Display	clear()	    Clears the screen.
+            Cls => self.gfx.cls(),
+            //0NNN	Call	            Calls machine code routine (RCA 1802 for COSMAC VIP) at address NNN. Not necessary for most ROMs.
+            Sys(nnn) => self.call(nnn, pc)?,
+            //1NNN	Flow	goto NNN;	Jumps to address NNN.
+            Jump(nnn) => self.program_counter = nnn,
             //2NNN	Flow	*(0xNNN)()	Calls subroutine at NNN.
-            2 => {
-                self.stack[self.stack_pointer as usize] = self.program_counter;
-                self.stack_pointer += 1;
-                self.program_counter = nnn;
-            }
+            Call(nnn) => self.call(nnn, pc)?,
             //3XNN	Cond	if(Vx==NN)	Skips the next instruction if VX equals NN. (Usually the next instruction is a jump to skip a code block)
-            3 => {
-                if self.vreg[x as usize] == nn {
-                    self.program_counter += 2;
+            SkipEqImm(x, nn) => {
+                if self.v(x) == nn {
+                    self.skip_next();
                 }
             }
             //4XNN	Cond	if(Vx!=NN)	Skips the next instruction if VX does not equal NN. (Usually the next instruction is a jump to skip a code block)
-            4 => {
-                if self.vreg[x as usize] != nn {
-                    self.program_counter += 2;
+            SkipNeImm(x, nn) => {
+                if self.v(x) != nn {
+                    self.skip_next();
                 }
             }
             //5XY0	Cond	if(Vx==Vy)	Skips the next instruction if VX equals VY. (Usually the next instruction is a jump to skip a code block)
-            5 => {
-                if self.vreg[x as usize] == self.vreg[y as usize] {
-                    self.program_counter += 2;
+            SkipEqReg(x, y) => {
+                if self.v(x) == self.v(y) {
+                    self.skip_next();
                 }
             }
-            //6XNN	Const	Vx = NN	    Sets VX to NN.
-            6 => self.vreg[x as usize] = nn,
-            //7XNN	Const	Vx += NN	Adds NN to VX. (Carry flag is not changed)
-            7 => self.vreg[x as usize] += nn,
-            8 => match n {
-                //8XY0	Assign	Vx=Vy	    Sets VX to the value of VY.
-                0 => self.vreg[x as usize] = self.vreg[y as usize],
-                //8XY1	BitOp	Vx=Vx|Vy	Sets VX to VX or VY. (Bitwise OR operation)
-                1 => self.vreg[x as usize] |= self.vreg[y as usize],
-                //8XY2	BitOp	Vx=Vx&Vy	Sets VX to VX and VY. (Bitwise AND operation)
-                2 => self.vreg[x as usize] &= self.vreg[y as usize],
-                //8XY3	BitOp	Vx=Vx^Vy	Sets VX to VX xor VY.
-                3 => self.vreg[x as usize] ^= self.vreg[y as usize],
-                //8XY4	Math	Vx += Vy	Adds VY to VX. VF is set to 1 when there's a carry, and to 0 when there is not.
-                4 => {
-                    match self.vreg[x as usize].checked_add(self.vreg[y as usize]) {
-                        Some(_) => self.vreg[0xF] = 1,
-                        None => self.vreg[0xF] = 0,
-                    }
-                    self.vreg[x as usize] =
-                        self.vreg[x as usize].wrapping_add(self.vreg[y as usize]);
+            //5XY2	MEM	    save(Vx..Vy)	Stores VX to VY (in either order) in memory starting at I, I is not changed. (XO-CHIP)
+            SaveRange(x, y) => {
+                let i = self.i() as usize;
+                let range = self.write_mem(i, x.abs_diff(y) as usize + 1)?;
+                for (addr, reg) in range.zip(Chip8::register_range(x, y)) {
+                    self.mem[addr] = self.v(reg);
                 }
-                //8XY5	Math	Vx -= Vy	VY is subtracted from VX. VF is set to 0 when there's a borrow, and 1 when there is not.
-                5 => {
-                    match self.vreg[x as usize].checked_sub(self.vreg[y as usize]) {
-                        Some(_) => self.vreg[0xF] = 0,
-                        None => self.vreg[0xF] = 1,
-                    }
-                    self.vreg[x as usize] =
-                        self.vreg[x as usize].wrapping_sub(self.vreg[y as usize]);
+            }
+            //5XY3	MEM	    load(Vx..Vy)	Fills VX to VY (in either order) from memory starting at I, I is not changed. (XO-CHIP)
+            LoadRange(x, y) => {
+                let i = self.i() as usize;
+                let range = self.read_mem(i, x.abs_diff(y) as usize + 1)?;
+                for (addr, reg) in range.zip(Chip8::register_range(x, y)) {
+                    self.set_v(reg, self.mem[addr]);
                 }
-                //8XY6	BitOp	Vx>>=1	    Stores the least significant bit of VX in VF and then shifts VX to the right by 1.[b]
-                6 => {
-                    self.vreg[0xF] = self.vreg[x as usize] & 0b00000001;
-                    self.vreg[x as usize] >>= 1;
+            }
+            //6XNN	Const	Vx = NN	    Sets VX to NN.
+            LoadImm(x, nn) => self.set_v(x, nn),
+            //7XNN	Const	Vx += NN	Adds NN to VX. (Carry flag is not changed)
+            AddImm(x, nn) => {
+                let value = self.v(x).wrapping_add(nn);
+                self.set_v(x, value);
+            }
+            //8XY0	Assign	Vx=Vy	    Sets VX to the value of VY.
+            Move(x, y) => {
+                let value = self.v(y);
+                self.set_v(x, value);
+            }
+            //8XY1	BitOp	Vx=Vx|Vy	Sets VX to VX or VY. (Bitwise OR operation)
+            Or(x, y) => {
+                let value = self.v(x) | self.v(y);
+                self.set_v(x, value);
+                if self.quirks.vf_reset {
+                    self.set_v(0xF, 0);
                 }
-                //8XY7	Math	Vx=Vy-Vx	Sets VX to VY minus VX. VF is set to 0 when there's a borrow, and 1 when there is not.
-                7 => {
-                    match self.vreg[y as usize].checked_sub(self.vreg[x as usize]) {
-                        Some(_) => self.vreg[0xF] = 0,
-                        None => self.vreg[0xF] = 1,
-                    }
-                    self.vreg[x as usize] =
-                        self.vreg[y as usize].wrapping_sub(self.vreg[x as usize]);
+            }
+            //8XY2	BitOp	Vx=Vx&Vy	Sets VX to VX and VY. (Bitwise AND operation)
+            And(x, y) => {
+                let value = self.v(x) & self.v(y);
+                self.set_v(x, value);
+                if self.quirks.vf_reset {
+                    self.set_v(0xF, 0);
                 }
-                //8XYE	BitOp	Vx<<=1	    Stores the most significant bit of VX in VF and then shifts VX to the left by 1.[b]
-                _ => {
-                    self.vreg[0xF] = (self.vreg[x as usize] >> 7) & 0b00000001;
-                    self.vreg[x as usize] <<= 1;
+            }
+            //8XY3	BitOp	Vx=Vx^Vy	Sets VX to VX xor VY.
+            Xor(x, y) => {
+                let value = self.v(x) ^ self.v(y);
+                self.set_v(x, value);
+                if self.quirks.vf_reset {
+                    self.set_v(0xF, 0);
                 }
-            },
+            }
+            //8XY4	Math	Vx += Vy	Adds VY to VX. VF is set to 1 when there's a carry, and to 0 when there is not.
+            // VF is written after VX in 8XY4, 8XY5 and 8XY7, so with X = F the flag is what remains.
+            AddReg(x, y) => {
+                let (result, carry) = self.v(x).overflowing_add(self.v(y));
+                self.set_v(x, result);
+                self.set_v(0xF, carry as u8);
+            }
+            //8XY5	Math	Vx -= Vy	VY is subtracted from VX. VF is set to 0 when there's a borrow, and 1 when there is not.
+            Sub(x, y) => {
+                let (result, borrow) = self.v(x).overflowing_sub(self.v(y));
+                self.set_v(x, result);
+                self.set_v(0xF, !borrow as u8);
+            }
+            //8XY6	BitOp	Vx>>=1	    Stores the least significant bit of VX in VF and then shifts VX to the right by 1.[b]
+            ShiftRight(x, y) => {
+                let value = self.v(if self.quirks.shift_vy { y } else { x });
+                self.set_v(x, value >> 1);
+                self.set_v(0xF, value & 0b00000001);
+            }
+            //8XY7	Math	Vx=Vy-Vx	Sets VX to VY minus VX. VF is set to 0 when there's a borrow, and 1 when there is not.
+            SubReverse(x, y) => {
+                let (result, borrow) = self.v(y).overflowing_sub(self.v(x));
+                self.set_v(x, result);
+                self.set_v(0xF, !borrow as u8);
+            }
+            //8XYE	BitOp	Vx<<=1	    Stores the most significant bit of VX in VF and then shifts VX to the left by 1.[b]
+            ShiftLeft(x, y) => {
+                let value = self.v(if self.quirks.shift_vy { y } else { x });
+                self.set_v(x, value << 1);
+                self.set_v(0xF, (value >> 7) & 0b00000001);
+            }
             //9XY0	Cond	if(Vx!=Vy)	Skips the next instruction if VX does not equal VY. (Usually the next instruction is a jump to skip a code block)
-            9 => {
-                if self.vreg[x as usize] != self.vreg[y as usize] {
-                    self.program_counter += 2;
+            SkipNeReg(x, y) => {
+                if self.v(x) != self.v(y) {
+                    self.skip_next();
                 }
             }
             //ANNN	MEM	    I = NNN	    Sets I to the address NNN.
-            0xA => self.ireg = nnn,
+            LoadI(nnn) => self.set_i(nnn),
             //BNNN	Flow	PC=V0+NNN	Jumps to the address NNN plus V0.
-            0xB => self.program_counter = self.vreg[0] as u16 + nnn,
+            //With the jump quirk it is read as BXNN and jumps to XNN plus VX instead.
+            JumpOffset(nnn) => {
+                let offset = self.v(if self.quirks.jump_vx {
+                    (nnn >> 8) as u8
+                } else {
+                    0
+                });
+                self.program_counter = offset as u16 + nnn;
+            }
             //CXNN	Rand	Vx=rand()&NN	Sets VX to the result of a bitwise and operation on a random number (Typically: 0 to 255) and NN.
-            0xC => self.vreg[x as usize] = thread_rng().gen_range(0..=255) & nn,
+            Random(x, nn) => {
+                let value = self.rng.gen::<u8>() & nn;
+                self.set_v(x, value);
+            }
             //DXYN	Disp	draw(Vx,Vy,N)
             //Draws a sprite at coordinate (VX, VY) that has a width of 8 pixels and a height of N+1 pixels.
+            //DXY0 draws a 16x16 sprite made of 32 bytes, two per row. (SUPER-CHIP)
+            //With both XO-CHIP planes selected the sprite for the second plane follows the first one.
             //Each row of 8 pixels is read as bit-coded starting from memory location I;
             //I value does not change after the execution of this instruction.
             //As described above, VF is set to 1 if any screen pixels are flipped from set to unset
             //when the sprite is drawn, and to 0 if that does not happen.
-            0xD => {
-                let x_coord = self.vreg[x as usize];
-                let y_coord = self.vreg[y as usize];
-                // for each 8 pixels array in memory
-                let mut sprite = vec![vec![0u8; n as usize]; 8];
-                for (index, row) in self.mem[self.ireg as usize..self.ireg as usize + n as usize]
-                    .iter()
-                    .enumerate()
-                {
-                    // for each pixel in array
-                    for i in 0..8 {
-                        sprite[7 - i][index] = (row >> i) & 0b00000001
+            Draw(x, y, n) => {
+                // Original hardware only drew during the vertical blank, so
+                // hold the PC on this instruction until the next 60Hz tick
+                if self.quirks.display_wait {
+                    if !self.vblank {
+                        self.program_counter = pc;
+                        return Ok(());
                     }
+                    self.vblank = false;
                 }
-                // let mut sprite = vec![vec![0u8; n as usize + 1]; 8];
-                // for (index, row) in self.mem[self.ireg as usize..(self.ireg + n as u16) as usize]
-                //     .into_iter()
-                //     .enumerate()
-                // {
-                //     // for each pixel in array
-                //     for i in 0..8 {
-                //         sprite[0 + i][index] = (row >> i) & 0b0001
-                //     }
-                // }
-                self.vreg[0xF] = self.gfx.draw_sprite(x_coord, y_coord, sprite) as u8;
-            }
-            0xE => match nn {
-                //EX9E	KeyOp	if(key()==Vx)	Skips the next instruction if the key stored in VX is pressed. (Usually the next instruction is a jump to skip a code block)
-                0x9E => {
-                    if self.keys[self.vreg[x as usize] as usize] > 0 {
-                        self.program_counter += 2;
+                let x_coord = self.v(x);
+                let y_coord = self.v(y);
+                let (width, height) = if n == 0 { (16, 16) } else { (8, n as usize) };
+                let bytes_per_row = width / 8;
+                let mut sprite_addr = self.i() as usize;
+                let mut collision = false;
+                for plane in [0b01, 0b10] {
+                    if self.gfx.planes & plane == 0 {
+                        continue;
                     }
-                }
-                //EXA1	KeyOp	if(key()!=Vx)	Skips the next instruction if the key stored in VX is not pressed. (Usually the next instruction is a jump to skip a code block)
-                _ => {
-                    if !(self.keys[self.vreg[x as usize] as usize] > 0) {
-                        self.program_counter += 2;
+                    // for each 8 pixels array in memory
+                    let mut sprite = vec![vec![0u8; height]; width];
+                    let range = self.read_mem(sprite_addr, height * bytes_per_row)?;
+                    let sprite_data = &self.mem[range];
+                    for (index, row) in sprite_data.iter().enumerate() {
+                        let y = index / bytes_per_row;
+                        let x_offset = index % bytes_per_row * 8;
+                        // for each pixel in array
+                        for i in 0..8 {
+                            sprite[x_offset + 7 - i][y] = (row >> i) & 0b00000001
+                        }
                     }
+                    sprite_addr += sprite_data.len();
+                    collision |=
+                        self.gfx
+                            .draw_sprite(x_coord, y_coord, sprite, self.quirks.clip, plane);
+                }
+                self.set_v(0xF, collision as u8);
+            }
+            //EX9E	KeyOp	if(key()==Vx)	Skips the next instruction if the key stored in VX is pressed. (Usually the next instruction is a jump to skip a code block)
+            SkipKey(x) => {
+                let key = self.v(x);
+                if self.key_pressed(key) {
+                    self.skip_next();
                 }
+            }
+            //EXA1	KeyOp	if(key()!=Vx)	Skips the next instruction if the key stored in VX is not pressed. (Usually the next instruction is a jump to skip a code block)
+            SkipNotKey(x) => {
+                let key = self.v(x);
+                if !self.key_pressed(key) {
+                    self.skip_next();
+                }
+            }
+            //F000 NNNN	MEM	I = NNNN	Sets I to the 16-bit address stored in the next two bytes. (XO-CHIP)
+            LoadILong => {
+                let range = self.mem_range(self.program_counter as usize, 2)?;
+                self.set_i((self.mem[range.start] as u16) << 8 | self.mem[range.start + 1] as u16);
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            //FN01	Display	plane(N)	Selects the bitplanes used by drawing, clearing and scrolling. (XO-CHIP)
+            Plane(n) => self.gfx.planes = n & 0b11,
+            //F002	Sound	audio()	Loads 16 bytes starting at I into the audio pattern buffer. (XO-CHIP)
+            Audio => {
+                let i = self.i() as usize;
+                let range = self.read_mem(i, 16)?;
+                self.audio_pattern.copy_from_slice(&self.mem[range]);
+            }
+            //FX07	Timer	Vx = get_delay()	Sets VX to the value of the delay timer.
+            GetDelay(x) => self.set_v(x, self.delay_timer),
+            //FX0A	KeyOp	Vx = get_key()	A key press is awaited, and then stored in VX. (Blocking Operation. All instruction halted until next key event)
+            WaitKey(x) => match self.keys.iter().position(|&k| k > 0) {
+                Some(key) => self.set_v(x, key as u8),
+                None => self.program_counter = pc,
             },
-            0xF => match nn {
-                //FX07	Timer	Vx = get_delay()	Sets VX to the value of the delay timer.
-                7 => self.vreg[x as usize] = self.delay_timer,
-                //FX0A	KeyOp	Vx = get_key()	A key press is awaited, and then stored in VX. (Blocking Operation. All instruction halted until next key event)
-                0xA => match self.keys.iter().enumerate().find(|(_, &k)| k > 0) {
-                    Some((i, _)) => self.vreg[x as usize] = i as u8,
-                    None => self.program_counter -= 2,
-                },
-                //FX15	Timer	delay_timer(Vx)	Sets the delay timer to VX.
-                0x15 => self.delay_timer = self.vreg[x as usize],
-                //FX18	Sound	sound_timer(Vx)	Sets the sound timer to VX.
-                0x18 => self.sound_timer = self.vreg[x as usize],
-                //FX1E	MEM	    I +=Vx	Adds VX to I. VF is not affected.[c]
-                0x1E => self.ireg += self.vreg[x as usize] as u16,
-                //FX29	MEM	    I=sprite_addr[Vx]	Sets I to the location of the sprite for the character in VX. Characters 0-F (in hexadecimal) are represented by a 4x5 font.
-                0x29 => self.ireg = (0x50 + self.vreg[x as usize] * 5) as u16,
-                //FX33	BCD	    Stores the binary-coded decimal representation of VX, with the most significant of three digits at the address in I, the middle digit at I plus 1, and the least significant digit at I plus 2. (In other words, take the decimal representation of VX, place the hundreds digit in memory at location in I, the tens digit at location I+1, and the ones digit at location I+2.)
-                0x33 => {
-                    //(251 / 10) % 10)
-                    let num = self.vreg[x as usize];
-                    self.mem[self.ireg as usize] = (num / 100) % 10;
-                    self.mem[self.ireg as usize + 1] = (num / 10) % 10;
-                    self.mem[self.ireg as usize + 2] = num % 10;
+            //FX15	Timer	delay_timer(Vx)	Sets the delay timer to VX.
+            SetDelay(x) => self.delay_timer = self.v(x),
+            //FX18	Sound	sound_timer(Vx)	Sets the sound timer to VX.
+            SetSound(x) => self.sound_timer = self.v(x),
+            //FX3A	Sound	pitch(Vx)	Sets the audio pattern playback rate to VX. (XO-CHIP)
+            Pitch(x) => self.pitch = self.v(x),
+            //FX1E	MEM	    I +=Vx	Adds VX to I. VF is not affected.[c]
+            AddI(x) => {
+                let value = self.i().wrapping_add(self.v(x) as u16);
+                self.set_i(value);
+            }
+            //FX29	MEM	    I=sprite_addr[Vx]	Sets I to the location of the sprite for the character in VX. Characters 0-F (in hexadecimal) are represented by a 4x5 font.
+            Font(x) => {
+                let value = 0x50 + (self.v(x) & 0xF) as u16 * 5;
+                self.set_i(value);
+            }
+            //FX30	MEM	    I=big_sprite_addr[Vx]	Sets I to the location of the 8x10 sprite for the digit in VX. (SUPER-CHIP)
+            BigFont(x) => {
+                let value = 0xA0 + (self.v(x) % 10) as u16 * 10;
+                self.set_i(value);
+            }
+            //FX33	BCD	    Stores the binary-coded decimal representation of VX, with the most significant of three digits at the address in I, the middle digit at I plus 1, and the least significant digit at I plus 2. (In other words, take the decimal representation of VX, place the hundreds digit in memory at location in I, the tens digit at location I+1, and the ones digit at location I+2.)
+            Bcd(x) => {
+                //(251 / 10) % 10)
+                let num = self.v(x);
+                let i = self.i() as usize;
+                let range = self.write_mem(i, 3)?;
+                self.mem[range].copy_from_slice(&[(num / 100) % 10, (num / 10) % 10, num % 10]);
+            }
+            //FX55	MEM	    reg_dump(Vx,&I)	Stores V0 to VX (including VX) in memory starting at address I. The offset from I is increased by 1 for each value written, but I itself is left unmodified.[d]
+            Store(x) => {
+                let i = self.i() as usize;
+                let range = self.write_mem(i, x as usize + 1)?;
+                for (addr, reg) in range.zip(0..=x) {
+                    self.mem[addr] = self.v(reg);
                 }
-                //FX55	MEM	    reg_dump(Vx,&I)	Stores V0 to VX (including VX) in memory starting at address I. The offset from I is increased by 1 for each value written, but I itself is left unmodified.[d]
-                0x55 => {
-                    // if self.vreg[x as usize] > 15 {
-                    //     panic!("{}", self.vreg[x as usize]);
-                    // };
-                    for reg in 0..=x {
-                        self.mem[(self.ireg + reg as u16) as usize] = self.vreg[reg as usize];
-                    }
-                    // for (i, v) in (0..std::cmp::min(self.vreg[x as usize], 16)).enumerate() {
-                    //     self.mem[(self.ireg + i as u16) as usize] = self.vreg[i]
-                    // }
+                self.increment_ireg_after_load_store(x);
+            }
+            //FX65	MEM	    reg_load(Vx,&I)	Fills V0 to VX (including VX) with values from memory starting at address I. The offset from I is increased by 1 for each value written, but I itself is left unmodified.[d]
+            Load(x) => {
+                let i = self.i() as usize;
+                let range = self.read_mem(i, x as usize + 1)?;
+                for (addr, reg) in range.zip(0..=x) {
+                    self.set_v(reg, self.mem[addr]);
                 }
-                //FX65	MEM	    reg_load(Vx,&I)	Fills V0 to VX (including VX) with values from memory starting at address I. The offset from I is increased by 1 for each value written, but I itself is left unmodified.[d]
-                _ => {
-                    // panic!("{:?}", 0..=self.vreg[x as usize]);
-                    for reg in 0..=x {
-                        self.vreg[reg as usize] = self.mem[(self.ireg + reg as u16) as usize];
-                    }
-                    // for (i, v) in (0..=std::cmp::min(self.vreg[x as usize], 15)).enumerate() {
-                    //     self.vreg[i] = self.mem[(self.ireg + i as u16) as usize]
-                    // }
-                    // self.ireg += std::cmp::min(self.vreg[x as usize], 15) as u16
+                self.increment_ireg_after_load_store(x);
+            }
+            //FX75	MEM	    rpl_dump(Vx)	Stores V0 to VX in the RPL user flags. (SUPER-CHIP, all 16 with XO-CHIP)
+            StoreRpl(x) => {
+                for reg in 0..=x {
+                    self.rpl[reg as usize] = self.v(reg);
                 }
-            },
-            _ => panic!("Should never happen, Chip8 dump: {:?}", self),
+            }
+            //FX85	MEM	    rpl_load(Vx)	Fills V0 to VX from the RPL user flags. (SUPER-CHIP, all 16 with XO-CHIP)
+            LoadRpl(x) => {
+                for reg in 0..=x {
+                    self.set_v(reg, self.rpl[reg as usize]);
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Pushes the return address and jumps to `addr`, `pc` is the calling instruction
+    fn call(&mut self, addr: u16, pc: u16) -> Result<(), ExecError> {
+        if self.stack_pointer as usize >= self.stack.len() {
+            return Err(ExecError::StackOverflow { pc });
+        }
+        self.stack[self.stack_pointer as usize] = self.program_counter;
+        self.stack_pointer += 1;
+        self.program_counter = addr;
+        Ok(())
+    }
+    /// Bounds checked range of `len` bytes of memory starting at `addr`
+    fn mem_range(&self, addr: usize, len: usize) -> Result<Range<usize>, ExecError> {
+        if addr + len > self.mem.len() {
+            return Err(ExecError::MemoryOutOfBounds {
+                addr: addr.max(self.mem.len()),
+            });
+        }
+        Ok(addr..addr + len)
+    }
+    /// Adds an access to the log, if one is kept
+    fn log(&mut self, kind: AccessKind, target: Target) {
+        if let Some(log) = &mut self.access_log {
+            log.accesses.push(Access { kind, target });
+        }
+    }
+    fn v(&mut self, x: u8) -> u8 {
+        self.log(AccessKind::Read, Target::V(x));
+        self.vreg[x as usize]
+    }
+    fn set_v(&mut self, x: u8, value: u8) {
+        self.log(AccessKind::Write, Target::V(x));
+        self.vreg[x as usize] = value;
+    }
+    fn i(&mut self) -> u16 {
+        self.log(AccessKind::Read, Target::I);
+        self.ireg
+    }
+    fn set_i(&mut self, value: u16) {
+        self.log(AccessKind::Write, Target::I);
+        self.ireg = value;
+    }
+    /// Bounds checked range of memory the instruction reads
+    fn read_mem(&mut self, addr: usize, len: usize) -> Result<Range<usize>, ExecError> {
+        let range = self.mem_range(addr, len)?;
+        self.log(AccessKind::Read, mem_target(&range));
+        Ok(range)
+    }
+    /// Bounds checked range of memory the instruction writes
+    fn write_mem(&mut self, addr: usize, len: usize) -> Result<Range<usize>, ExecError> {
+        let range = self.mem_range(addr, len)?;
+        self.log(AccessKind::Write, mem_target(&range));
+        if let Some(log) = &mut self.access_log {
+            log.overwritten.extend_from_slice(&self.mem[range.clone()]);
         }
+        self.mark_dirty(range.clone());
+        Ok(range)
+    }
+    /// Keys past F are never pressed
+    fn key_pressed(&self, key: u8) -> bool {
+        self.keys.get(key as usize).is_some_and(|&k| k > 0)
+    }
+
+    fn increment_ireg_after_load_store(&mut self, x: u8) {
+        match self.quirks.load_store {
+            LoadStore::Unchanged => {}
+            LoadStore::IncrementX => self.set_i(self.ireg.wrapping_add(x as u16)),
+            LoadStore::IncrementXPlusOne => self.set_i(self.ireg.wrapping_add(x as u16 + 1)),
+        }
+    }
+
+    /// Skips the next instruction, which is four bytes long if it is the XO-CHIP F000 NNNN
+    fn skip_next(&mut self) {
+        let pc = self.program_counter as usize;
+        let long_load = self.platform == Platform::XoChip
+            && self.mem.get(pc) == Some(&0xF0)
+            && self.mem.get(pc + 1) == Some(&0x00);
+        self.program_counter = self
+            .program_counter
+            .wrapping_add(if long_load { 4 } else { 2 });
+    }
+    /// Registers X to Y inclusive, walking backwards when X is greater than Y
+    pub(crate) fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = u8>> {
+        if x <= y {
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
+        }
+    }
+}
+
+fn mem_target(range: &Range<usize>) -> Target {
+    Target::Mem {
+        first: range.start as u16,
+        last: (range.end - 1) as u16,
     }
 }
@@ -0,0 +1,268 @@
+//! Which bytes of memory ran as instructions, were read as data (by DXYN,
+//! FX33, FX65 and the like) or were written.
+//!
+//! A coverage file lists every touched address with its flags, `x` for
+//! executed, `r` for read and `w` for written:
+//!
+//! ```text
+//! chipterm-coverage 1
+//! rom 510ced63295af96f
+//! runs 2
+//! 0200 x--
+//! 0201 x--
+//! 02A0 -r-
+//! 0F00 --w
+//! ```
+//!
+//! Files of the same ROM merge by combining the flags of each address, so
+//! several runs add up to the coverage of a test suite. Blank lines and `;`
+//! comments are ignored.
+
+use std::{
+    error, fmt,
+    io::{self, BufRead, Write},
+    ops::Range,
+};
+
+use crate::access::{Access, AccessKind, Target};
+use crate::instruction::Instruction;
+
+pub const VERSION: u32 = 1;
+
+pub const EXECUTED: u8 = 1;
+pub const READ: u8 = 2;
+pub const WRITTEN: u8 = 4;
+
+#[derive(Debug)]
+pub enum CoverageError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for CoverageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoverageError::Io(err) => write!(f, "{}", err),
+            CoverageError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl error::Error for CoverageError {}
+
+impl From<io::Error> for CoverageError {
+    fn from(err: io::Error) -> Self {
+        CoverageError::Io(err)
+    }
+}
+
+/// Byte counts over an address range. A byte both run and read counts for both.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub len: usize,
+    pub executed: usize,
+    pub read: usize,
+    pub written: usize,
+    pub untouched: usize,
+}
+
+impl Summary {
+    /// `count` as a share of the range, in percent
+    pub fn percent(&self, count: usize) -> f64 {
+        if self.len == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / self.len as f64
+        }
+    }
+}
+
+/// Access flags per byte of memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coverage {
+    pub rom_hash: u64,
+    /// Runs merged into this coverage
+    pub runs: u32,
+    flags: Vec<u8>,
+}
+
+impl Coverage {
+    /// No bytes touched yet in `len` bytes of memory
+    pub fn new(rom_hash: u64, len: usize) -> Self {
+        Coverage {
+            rom_hash,
+            runs: 1,
+            flags: vec![0; len],
+        }
+    }
+
+    /// Flags the instruction `opcode` that ran at `pc` and the bytes it
+    /// accessed, as logged by `Chip8::accesses`
+    pub fn record(&mut self, pc: u16, opcode: u16, accesses: &[Access]) {
+        // LD I, NNNN carries its address in the next two bytes
+        let len = match Instruction::decode(opcode) {
+            Some(Instruction::LoadILong) => 4,
+            _ => 2,
+        };
+        self.mark(pc as usize..pc as usize + len, EXECUTED);
+        for access in accesses {
+            if let Target::Mem { first, last } = access.target {
+                let flag = match access.kind {
+                    AccessKind::Read => READ,
+                    AccessKind::Write => WRITTEN,
+                };
+                self.mark(first as usize..last as usize + 1, flag);
+            }
+        }
+    }
+
+    fn mark(&mut self, range: Range<usize>, flag: u8) {
+        let end = range.end.min(self.flags.len());
+        for flags in &mut self.flags[range.start.min(end)..end] {
+            *flags |= flag;
+        }
+    }
+
+    /// The flags of the byte at `addr`
+    pub fn flags(&self, addr: u16) -> u8 {
+        self.flags.get(addr as usize).copied().unwrap_or(0)
+    }
+
+    /// Adds the flags and runs of `other`, which should cover the same ROM
+    pub fn merge(&mut self, other: &Coverage) {
+        if other.flags.len() > self.flags.len() {
+            self.flags.resize(other.flags.len(), 0);
+        }
+        for (flags, &other) in self.flags.iter_mut().zip(&other.flags) {
+            *flags |= other;
+        }
+        self.runs += other.runs;
+    }
+
+    /// Counts the flagged bytes in `range`
+    pub fn summary(&self, range: Range<usize>) -> Summary {
+        let mut summary = Summary {
+            len: range.len(),
+            ..Summary::default()
+        };
+        for addr in range {
+            let flags = self.flags.get(addr).copied().unwrap_or(0);
+            if flags & EXECUTED != 0 {
+                summary.executed += 1;
+            }
+            if flags & READ != 0 {
+                summary.read += 1;
+            }
+            if flags & WRITTEN != 0 {
+                summary.written += 1;
+            }
+            if flags == 0 {
+                summary.untouched += 1;
+            }
+        }
+        summary
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "chipterm-coverage {}", VERSION)?;
+        writeln!(out, "rom {:016x}", self.rom_hash)?;
+        writeln!(out, "runs {}", self.runs)?;
+        for (addr, &flags) in self.flags.iter().enumerate() {
+            if flags != 0 {
+                writeln!(out, "{:04X} {}", addr, flag_chars(flags))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read(input: impl BufRead) -> Result<Coverage, CoverageError> {
+        // Header lines read so far
+        let mut header = 0;
+        let mut coverage = Coverage::new(0, 0);
+        let mut n = 0;
+        for line in input.lines() {
+            n += 1;
+            let line = line?;
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match (header, &words[..]) {
+                (0, ["chipterm-coverage", version]) => {
+                    if *version != VERSION.to_string() {
+                        return Err(parse_error(
+                            n,
+                            format!(
+                                "coverage version {} is not supported, expected version {}",
+                                version, VERSION
+                            ),
+                        ));
+                    }
+                    header += 1;
+                }
+                (1, ["rom", hash]) => {
+                    coverage.rom_hash = u64::from_str_radix(hash, 16)
+                        .map_err(|_| parse_error(n, format!("bad hash '{}'", hash)))?;
+                    header += 1;
+                }
+                (2, ["runs", runs]) => {
+                    coverage.runs = runs
+                        .parse()
+                        .map_err(|_| parse_error(n, format!("bad run count '{}'", runs)))?;
+                    header += 1;
+                }
+                (3, [addr, flags]) => {
+                    let addr = u16::from_str_radix(addr, 16)
+                        .map_err(|_| parse_error(n, format!("bad address '{}'", addr)))?;
+                    let flags = parse_flags(flags)
+                        .ok_or_else(|| parse_error(n, format!("bad flags '{}'", flags)))?;
+                    if addr as usize >= coverage.flags.len() {
+                        coverage.flags.resize(addr as usize + 1, 0);
+                    }
+                    coverage.flags[addr as usize] |= flags;
+                }
+                (0, _) => return Err(parse_error(n, "not a coverage file")),
+                _ => return Err(parse_error(n, format!("unexpected '{}'", line))),
+            }
+        }
+        if header < 3 {
+            let missing = ["header", "rom", "runs"][header];
+            return Err(parse_error(n + 1, format!("missing {}", missing)));
+        }
+        Ok(coverage)
+    }
+}
+
+/// `x`, `r` and `w` for the set flags, `-` for the others
+fn flag_chars(flags: u8) -> String {
+    [(EXECUTED, 'x'), (READ, 'r'), (WRITTEN, 'w')]
+        .iter()
+        .map(|&(flag, c)| if flags & flag != 0 { c } else { '-' })
+        .collect()
+}
+
+fn parse_flags(s: &str) -> Option<u8> {
+    if s.len() != 3 {
+        return None;
+    }
+    let mut flags = 0;
+    for (c, (flag, set)) in s
+        .chars()
+        .zip([(EXECUTED, 'x'), (READ, 'r'), (WRITTEN, 'w')].iter())
+    {
+        match c {
+            '-' => {}
+            c if c == *set => flags |= flag,
+            _ => return None,
+        }
+    }
+    Some(flags)
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> CoverageError {
+    CoverageError::Parse {
+        line,
+        message: message.into(),
+    }
+}
@@ -0,0 +1,670 @@
+//! Debug Adapter Protocol server, for debugging ROMs from editors.
+//!
+//! The editor launches a ROM with
+//!
+//! ```json
+//! { "program": "pong.ch8", "sourceMap": "pong.map", "symbols": "pong.sym",
+//!   "platform": "chip8", "quirks": "vip", "seed": 1, "cyclesPerFrame": 12,
+//!   "stopOnEntry": true }
+//! ```
+//!
+//! where only `program` is required. Breakpoints can be set by source line
+//! when a source map from `chipterm asm --source-map` is given, and by address
+//! or label as function or instruction breakpoints. The emulator runs
+//! headless, there is no display or keypad.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, RecvTimeoutError},
+    thread,
+};
+
+use serde_json::{json, Value};
+
+use crate::core::{Chip8, ExecError};
+use crate::debugger::{parse_address, Debugger, Stop};
+use crate::instruction::Instruction;
+use crate::platform::Platform;
+use crate::quirks::Preset;
+use crate::scheduler::Scheduler;
+use crate::symbols::{SourceMap, Symbols};
+
+/// The emulator is the only thread
+const THREAD_ID: u64 = 1;
+
+/// Variable references of the scopes, memory pages start at `PAGES`
+const REGISTERS: u64 = 1;
+const TIMERS: u64 = 2;
+const MEMORY: u64 = 3;
+const PAGES: u64 = 0x100;
+
+const PAGE_SIZE: usize = 0x100;
+const ROW_SIZE: usize = 0x10;
+
+/// Reads a `Content-Length` framed message, or returns None at the end of the input
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut len = None;
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if input.read_line(&mut header)? == 0 {
+            return match len {
+                None if header.is_empty() => Ok(None),
+                _ => Err(io::ErrorKind::UnexpectedEof.into()),
+            };
+        }
+        let line = header.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                len = Some(value.trim().parse::<usize>().map_err(invalid_data)?);
+            }
+        }
+    }
+    let len = len.ok_or_else(|| invalid_data("missing Content-Length header"))?;
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(invalid_data)
+}
+
+pub fn write_message(out: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    let message = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+    out.write_all(message.as_bytes())?;
+    out.flush()
+}
+
+/// Serves one debugging session, until the editor disconnects or closes `input`
+pub fn serve(input: impl Read + Send + 'static, mut output: impl Write) -> io::Result<()> {
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        let mut input = BufReader::new(input);
+        loop {
+            let message = read_message(&mut input);
+            let last = !matches!(message, Ok(Some(_)));
+            if tx.send(message).is_err() || last {
+                return;
+            }
+        }
+    });
+
+    let mut session = Session::default();
+    loop {
+        let message = if session.running {
+            if session.scheduler.frame_due() {
+                session.run_frame();
+                None
+            } else {
+                match rx.recv_timeout(session.scheduler.timeout()) {
+                    Ok(message) => Some(message),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        } else {
+            match rx.recv() {
+                Ok(message) => Some(message),
+                Err(_) => break,
+            }
+        };
+        match message.transpose()? {
+            Some(Some(request)) => session.handle(&request),
+            Some(None) => break,
+            None => {}
+        }
+        for message in session.outgoing.drain(..) {
+            write_message(&mut output, &message)?;
+        }
+        if session.done {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Where a step over or out ends
+#[derive(Debug, Clone, Copy)]
+enum StepUntil {
+    /// Back from the subroutine called at `pc - 2`
+    Return { pc: u16, depth: u16 },
+    /// Out of the current subroutine
+    Depth(u16),
+}
+
+#[derive(Debug)]
+struct Session {
+    seq: u64,
+    /// Responses and events to send
+    outgoing: Vec<Value>,
+    chip8: Option<Chip8>,
+    scheduler: Scheduler,
+    debugger: Debugger,
+    symbols: Symbols,
+    source_map: SourceMap,
+    /// Breakpoints by source file, function and instruction breakpoints
+    /// replace their whole set each time so they are kept apart
+    source_breakpoints: HashMap<PathBuf, Vec<u16>>,
+    function_breakpoints: Vec<u16>,
+    instruction_breakpoints: Vec<u16>,
+    stop_on_entry: bool,
+    running: bool,
+    /// Set on resume so the instruction the debugger stopped at can run
+    resuming: bool,
+    step_until: Option<StepUntil>,
+    done: bool,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            seq: 0,
+            outgoing: Vec::new(),
+            chip8: None,
+            scheduler: Scheduler::new(12),
+            debugger: Debugger::new(),
+            symbols: Symbols::new(),
+            source_map: SourceMap::new(),
+            source_breakpoints: HashMap::new(),
+            function_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+            stop_on_entry: false,
+            running: false,
+            resuming: false,
+            step_until: None,
+            done: false,
+        }
+    }
+}
+
+impl Session {
+    fn send(&mut self, mut message: Value) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        self.outgoing.push(message);
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    fn stopped(&mut self, reason: &str, description: String) {
+        self.running = false;
+        self.step_until = None;
+        self.event(
+            "stopped",
+            json!({
+                "reason": reason,
+                "description": description,
+                "threadId": THREAD_ID,
+                "allThreadsStopped": true,
+            }),
+        );
+    }
+
+    fn handle(&mut self, request: &Value) {
+        if request["type"] != "request" {
+            return;
+        }
+        let command = request["command"].as_str().unwrap_or("");
+        let args = &request["arguments"];
+        let result = match command {
+            "initialize" => Ok(capabilities()),
+            "launch" => self.launch(args),
+            "disconnect" | "terminate" => {
+                self.done = true;
+                Ok(Value::Null)
+            }
+            _ if self.chip8.is_none() => Err(String::from("no ROM launched")),
+            "configurationDone" => Ok(Value::Null),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(scopes()),
+            "variables" => self.variables(args),
+            "readMemory" => self.read_memory(args),
+            "continue" => {
+                self.resume(None);
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "pause" | "next" | "stepIn" | "stepOut" => Ok(Value::Null),
+            _ => Err(format!("unsupported request '{}'", command)),
+        };
+        let mut response = match result {
+            Ok(body) => json!({ "success": true, "body": body }),
+            Err(message) => json!({ "success": false, "message": message }),
+        };
+        response["type"] = json!("response");
+        response["request_seq"] = request["seq"].clone();
+        response["command"] = json!(command);
+        let success = response["success"] == true;
+        self.send(response);
+
+        // Events must follow the response they result from
+        match command {
+            "launch" if success => self.event("initialized", json!({})),
+            "configurationDone" if success => {
+                if self.stop_on_entry {
+                    self.stopped("entry", String::from("Stopped at the first instruction"));
+                } else {
+                    self.resume(None);
+                }
+            }
+            "pause" if success => self.stopped("pause", String::from("Paused")),
+            "next" if success => self.step_over(),
+            "stepIn" if success => self.step_in(),
+            "stepOut" if success => self.step_out(),
+            "terminate" => self.event("terminated", json!({})),
+            _ => {}
+        }
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = args["program"]
+            .as_str()
+            .ok_or_else(|| String::from("launch needs a 'program' ROM path"))?;
+        let rom = fs::read(program).map_err(|err| format!("{}: {}", program, err))?;
+        let platform = match args["platform"].as_str() {
+            Some(platform) => platform.parse::<Platform>()?,
+            None => Platform::Chip8,
+        };
+        let preset = match args["quirks"].as_str() {
+            Some(preset) => preset.parse::<Preset>()?,
+            None => platform.default_preset(),
+        };
+        let seed = args["seed"].as_u64().unwrap_or_else(rand::random);
+        let mut chip8 = Chip8::new(platform, preset.quirks(), seed);
+        chip8
+            .load_game(&rom)
+            .map_err(|err| format!("{}: {}", program, err))?;
+
+        if let Some(path) = args["symbols"].as_str() {
+            self.symbols =
+                Symbols::read(open(path)?).map_err(|err| format!("{}: {}", path, err))?;
+        }
+        if let Some(path) = args["sourceMap"].as_str() {
+            let mut map =
+                SourceMap::read(open(path)?).map_err(|err| format!("{}: {}", path, err))?;
+            map.resolve_paths(Path::new(path).parent().unwrap_or_else(|| Path::new("")));
+            self.source_map = map;
+        }
+        let cycles_per_frame = args["cyclesPerFrame"].as_u64().unwrap_or(12);
+        self.scheduler = Scheduler::new(cycles_per_frame.clamp(1, u32::MAX as u64) as u32);
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        self.chip8 = Some(chip8);
+        Ok(Value::Null)
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["source"]["path"]
+            .as_str()
+            .ok_or_else(|| String::from("breakpoints need a source path"))?;
+        let path = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        let lines: Vec<u64> = match args["breakpoints"].as_array() {
+            Some(breakpoints) => breakpoints
+                .iter()
+                .filter_map(|b| b["line"].as_u64())
+                .collect(),
+            None => Vec::new(),
+        };
+        let mut addrs = Vec::new();
+        let mut breakpoints = Vec::new();
+        for line in lines {
+            match self.source_map.address(&path, line as usize) {
+                Some((addr, line)) => {
+                    addrs.push(addr);
+                    breakpoints.push(json!({
+                        "verified": true,
+                        "line": line,
+                        "instructionReference": reference(addr),
+                    }));
+                }
+                None => breakpoints.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": if self.source_map.is_empty() {
+                        "no source map, launch with 'sourceMap' to break on lines"
+                    } else {
+                        "no instruction at or after this line"
+                    },
+                })),
+            }
+        }
+        self.source_breakpoints.insert(path, addrs);
+        self.update_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    /// Breakpoints named by address or label
+    fn set_function_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let names = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        self.function_breakpoints.clear();
+        let mut breakpoints = Vec::new();
+        for name in names.iter().filter_map(|b| b["name"].as_str()) {
+            match self
+                .symbols
+                .address(name)
+                .ok_or(())
+                .or_else(|()| parse_address(name))
+            {
+                Ok(addr) => {
+                    self.function_breakpoints.push(addr);
+                    breakpoints.push(self.verified(addr));
+                }
+                Err(_) => breakpoints.push(json!({
+                    "verified": false,
+                    "message": format!("no label or address '{}'", name),
+                })),
+            }
+        }
+        self.update_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        self.instruction_breakpoints.clear();
+        let mut breakpoints = Vec::new();
+        for breakpoint in &requested {
+            let reference = breakpoint["instructionReference"].as_str().unwrap_or("");
+            let offset = breakpoint["offset"].as_i64().unwrap_or(0);
+            match parse_address(reference) {
+                Ok(addr) => {
+                    let addr = (addr as i64 + offset) as u16;
+                    self.instruction_breakpoints.push(addr);
+                    breakpoints.push(self.verified(addr));
+                }
+                Err(message) => breakpoints.push(json!({ "verified": false, "message": message })),
+            }
+        }
+        self.update_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn verified(&self, addr: u16) -> Value {
+        let mut breakpoint = json!({ "verified": true, "instructionReference": reference(addr) });
+        if let Some((path, line)) = self.source_map.location(addr) {
+            breakpoint["source"] = source(path);
+            breakpoint["line"] = json!(line);
+        }
+        breakpoint
+    }
+
+    fn update_breakpoints(&mut self) {
+        self.debugger.clear_breakpoints();
+        let addrs = self
+            .source_breakpoints
+            .values()
+            .flatten()
+            .chain(&self.function_breakpoints)
+            .chain(&self.instruction_breakpoints);
+        for &addr in addrs {
+            self.debugger.add_breakpoint(addr);
+        }
+    }
+
+    /// The instruction about to run, then a frame per subroutine call on the stack
+    fn stack_trace(&self) -> Value {
+        let chip8 = self.chip8.as_ref().expect("launched");
+        let calls = chip8.stack[..chip8.stack_pointer as usize]
+            .iter()
+            .rev()
+            .map(|&ret| ret.wrapping_sub(2));
+        let frames: Vec<Value> = std::iter::once(chip8.program_counter)
+            .chain(calls)
+            .enumerate()
+            .map(|(id, addr)| {
+                let mut frame = json!({
+                    "id": id,
+                    "name": self.symbols.describe(addr),
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": reference(addr),
+                });
+                if let Some((path, line)) = self.source_map.location(addr) {
+                    frame["source"] = source(path);
+                    frame["line"] = json!(line);
+                    frame["column"] = json!(1);
+                }
+                frame
+            })
+            .collect();
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    fn variables(&self, args: &Value) -> Result<Value, String> {
+        let chip8 = self.chip8.as_ref().expect("launched");
+        let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        let variables = match args["variablesReference"].as_u64().unwrap_or(0) {
+            REGISTERS => {
+                let mut variables: Vec<Value> = chip8
+                    .vreg
+                    .iter()
+                    .enumerate()
+                    .map(|(x, v)| variable(format!("V{:X}", x), format!("{:#04X}", v)))
+                    .collect();
+                let mut ireg = variable(String::from("I"), format!("{:#05X}", chip8.ireg));
+                ireg["memoryReference"] = json!(reference(chip8.ireg));
+                variables.push(ireg);
+                let mut pc = variable(
+                    String::from("PC"),
+                    format!(
+                        "{:#05X} {}",
+                        chip8.program_counter,
+                        self.symbols.describe(chip8.program_counter)
+                    ),
+                );
+                pc["memoryReference"] = json!(reference(chip8.program_counter));
+                variables.push(pc);
+                variables.push(variable(
+                    String::from("SP"),
+                    format!("{}/{}", chip8.stack_pointer, chip8.stack.len()),
+                ));
+                variables
+            }
+            TIMERS => vec![
+                variable(String::from("DT"), chip8.delay_timer.to_string()),
+                variable(String::from("ST"), chip8.sound_timer.to_string()),
+            ],
+            MEMORY => (0..chip8.mem.len())
+                .step_by(PAGE_SIZE)
+                .map(|start| {
+                    let end = (start + PAGE_SIZE).min(chip8.mem.len()) - 1;
+                    json!({
+                        "name": format!("{:#06X}", start),
+                        "value": format!("{:#06X}-{:#06X}", start, end),
+                        "variablesReference": PAGES + (start / PAGE_SIZE) as u64,
+                        "memoryReference": reference(start as u16),
+                    })
+                })
+                .collect(),
+            page if page >= PAGES && page - PAGES < pages(chip8) => {
+                let start = (page - PAGES) as usize * PAGE_SIZE;
+                let end = (start + PAGE_SIZE).min(chip8.mem.len());
+                (start..end)
+                    .step_by(ROW_SIZE)
+                    .map(|row| {
+                        let bytes: Vec<String> = chip8.mem[row..(row + ROW_SIZE).min(end)]
+                            .iter()
+                            .map(|byte| format!("{:02X}", byte))
+                            .collect();
+                        variable(format!("{:#06X}", row), bytes.join(" "))
+                    })
+                    .collect()
+            }
+            reference => return Err(format!("unknown variables reference {}", reference)),
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn read_memory(&self, args: &Value) -> Result<Value, String> {
+        let chip8 = self.chip8.as_ref().expect("launched");
+        let addr = parse_address(args["memoryReference"].as_str().unwrap_or(""))? as i64
+            + args["offset"].as_i64().unwrap_or(0);
+        let count = args["count"].as_u64().unwrap_or(0) as usize;
+        let start = addr.clamp(0, chip8.mem.len() as i64) as usize;
+        let end = start.saturating_add(count).min(chip8.mem.len());
+        Ok(json!({
+            "address": reference(start as u16),
+            "data": base64(&chip8.mem[start..end]),
+            "unreadableBytes": count - (end - start),
+        }))
+    }
+
+    fn resume(&mut self, step_until: Option<StepUntil>) {
+        self.running = true;
+        self.resuming = true;
+        self.step_until = step_until;
+    }
+
+    fn step_in(&mut self) {
+        let chip8 = self.chip8.as_mut().expect("launched");
+        let result = self.debugger.step(chip8);
+        if !self.after_step(result) {
+            self.stopped("step", String::from("Stepped"));
+        }
+    }
+
+    /// Runs a subroutine call to its return, steps any other instruction
+    fn step_over(&mut self) {
+        let chip8 = self.chip8.as_ref().expect("launched");
+        let pc = chip8.program_counter;
+        let opcode = match (chip8.mem.get(pc as usize), chip8.mem.get(pc as usize + 1)) {
+            (Some(&hi), Some(&lo)) => (hi as u16) << 8 | lo as u16,
+            _ => 0,
+        };
+        match Instruction::decode(opcode) {
+            Some(Instruction::Call(_)) | Some(Instruction::Sys(_)) => {
+                self.resume(Some(StepUntil::Return {
+                    pc: pc.wrapping_add(2),
+                    depth: chip8.stack_pointer,
+                }))
+            }
+            _ => self.step_in(),
+        }
+    }
+
+    fn step_out(&mut self) {
+        match self.chip8.as_ref().expect("launched").stack_pointer {
+            0 => self.step_in(),
+            depth => self.resume(Some(StepUntil::Depth(depth))),
+        }
+    }
+
+    /// Reports a stop, an error or the end of the program, returns whether there was one
+    fn after_step(&mut self, result: Result<Option<Stop>, ExecError>) -> bool {
+        match result {
+            Ok(Some(stop)) => self.stopped("data breakpoint", stop.to_string()),
+            Ok(None) if self.chip8.as_ref().is_some_and(|chip8| chip8.exited) => {
+                self.running = false;
+                self.event("exited", json!({ "exitCode": 0 }));
+                self.event("terminated", json!({}));
+            }
+            Ok(None) => return false,
+            Err(err) => self.stopped("exception", err.to_string()),
+        }
+        true
+    }
+
+    /// Runs a 60Hz frame worth of instructions until something stops them
+    fn run_frame(&mut self) {
+        for _ in 0..self.scheduler.cycles_per_frame {
+            let chip8 = self.chip8.as_mut().expect("launched");
+            if !std::mem::take(&mut self.resuming) {
+                if let Some(stop) = self.debugger.check(chip8) {
+                    self.stopped("breakpoint", stop.to_string());
+                    return;
+                }
+            }
+            let result = self.debugger.step(chip8);
+            let (pc, depth) = (chip8.program_counter, chip8.stack_pointer);
+            if self.after_step(result) {
+                return;
+            }
+            let arrived = match self.step_until {
+                Some(StepUntil::Return { pc: ret, depth: d }) => pc == ret && depth == d,
+                Some(StepUntil::Depth(d)) => depth < d,
+                None => false,
+            };
+            if arrived {
+                self.stopped("step", String::from("Stepped"));
+                return;
+            }
+        }
+        if let Some(chip8) = &mut self.chip8 {
+            chip8.end_frame();
+        }
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "supportsConfigurationDoneRequest": true,
+        "supportsFunctionBreakpoints": true,
+        "supportsInstructionBreakpoints": true,
+        "supportsReadMemoryRequest": true,
+        "supportsTerminateRequest": true,
+    })
+}
+
+fn scopes() -> Value {
+    json!({ "scopes": [
+        { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+        { "name": "Timers", "variablesReference": TIMERS, "expensive": false },
+        { "name": "Memory", "variablesReference": MEMORY, "expensive": true },
+    ]})
+}
+
+fn source(path: &Path) -> Value {
+    let name = path.file_name().map_or_else(
+        || path.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    json!({ "name": name, "path": path.display().to_string() })
+}
+
+/// Number of memory pages, the last one may be short
+fn pages(chip8: &Chip8) -> u64 {
+    chip8.mem.len().div_ceil(PAGE_SIZE) as u64
+}
+
+/// Addresses as memory and instruction references
+fn reference(addr: u16) -> String {
+    format!("{:#06X}", addr)
+}
+
+fn open(path: &str) -> Result<BufReader<File>, String> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| format!("{}: {}", path, err))
+}
+
+fn invalid_data(err: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,342 @@
+use std::{collections::BTreeSet, fmt, str::FromStr};
+
+use crate::access::{Access, AccessKind, Target};
+use crate::core::{Chip8, ExecError};
+use crate::instruction::Instruction;
+
+/// Why the debugger stopped the emulator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(u16),
+    /// The instruction at `pc` read or wrote `value` at `target`, which `watchpoint` covers
+    Watchpoint {
+        watchpoint: Watchpoint,
+        kind: AccessKind,
+        pc: u16,
+        opcode: u16,
+        target: Target,
+        value: u16,
+    },
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::Breakpoint(addr) => write!(f, "Breakpoint at {:#05X} hit", addr),
+            Stop::Watchpoint {
+                watchpoint,
+                kind,
+                pc,
+                opcode,
+                target,
+                value,
+            } => {
+                let kind = match kind {
+                    AccessKind::Read => "Read",
+                    AccessKind::Write => "Write",
+                };
+                let instruction = Instruction::decode(*opcode)
+                    .map_or_else(|| String::from("???"), |i| i.to_string());
+                write!(
+                    f,
+                    "{} watchpoint '{}' hit by {:#05X} {:04X} {}, {} = {:#04X}",
+                    kind, watchpoint, pc, opcode, instruction, target, value
+                )
+            }
+        }
+    }
+}
+
+/// A read and/or write watch on a register or a range of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub target: Target,
+    pub read: bool,
+    pub write: bool,
+    /// Only trigger when the value read or written equals this
+    pub value: Option<u16>,
+}
+
+/// The registers and memory an access is matched against
+struct Values<'a> {
+    vreg: &'a [u8; 16],
+    ireg: u16,
+    mem: &'a dyn Fn(u16) -> Option<u8>,
+}
+
+impl Watchpoint {
+    /// The watched location `accessed` covers and its value, if the value condition holds
+    fn matches(&self, values: &Values, accessed: Target) -> Option<(Target, u16)> {
+        let wanted = |value: u16| self.value.is_none_or(|wanted| wanted == value);
+        match (self.target, accessed) {
+            (Target::V(a), Target::V(b)) if a == b => {
+                Some((Target::V(a), values.vreg[a as usize] as u16)).filter(|&(_, v)| wanted(v))
+            }
+            (Target::I, Target::I) => Some((Target::I, values.ireg)).filter(|&(_, v)| wanted(v)),
+            (
+                Target::Mem {
+                    first: a_first,
+                    last: a_last,
+                },
+                Target::Mem {
+                    first: b_first,
+                    last: b_last,
+                },
+            ) => (a_first.max(b_first)..=a_last.min(b_last))
+                .filter_map(|addr| (values.mem)(addr).map(|v| (addr, v as u16)))
+                .find(|&(_, v)| wanted(v))
+                .map(|(addr, v)| {
+                    (
+                        Target::Mem {
+                            first: addr,
+                            last: addr,
+                        },
+                        v,
+                    )
+                }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match (self.read, self.write) {
+            (true, true) => "rw",
+            (true, false) => "r",
+            _ => "w",
+        };
+        write!(f, "{} {}", mode, self.target)?;
+        if let Some(value) = self.value {
+            write!(f, " = {:#04X}", value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses `[r|w|rw] <target>[=<value>]` where the target is `V0`-`VF`, `I`,
+/// an address or an address range like `0x300-0x305`. Watches writes when no
+/// mode is given.
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (spec, value) = match s.split_once('=') {
+            Some((spec, value)) => (spec, Some(parse_value(value)?)),
+            None => (s, None),
+        };
+        let mut words = spec.split_whitespace();
+        let (read, write, target) = match (words.next(), words.next(), words.next()) {
+            (Some("r"), Some(target), None) => (true, false, target),
+            (Some("w"), Some(target), None) => (false, true, target),
+            (Some("rw"), Some(target), None) => (true, true, target),
+            (Some(target), None, None) => (false, true, target),
+            _ => {
+                return Err(format!(
+                    "invalid watchpoint '{}', expected [r|w|rw] <target>[=<value>]",
+                    s.trim()
+                ))
+            }
+        };
+        let target = match target.to_ascii_uppercase().as_str() {
+            "I" => Target::I,
+            reg if reg.len() == 2 && reg.starts_with('V') => Target::V(
+                u8::from_str_radix(&reg[1..], 16)
+                    .map_err(|_| format!("invalid register '{}'", target))?,
+            ),
+            _ => {
+                let (start, end) = match target.split_once('-') {
+                    Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+                    None => {
+                        let addr = parse_address(target)?;
+                        (addr, addr)
+                    }
+                };
+                if end < start {
+                    return Err(format!("invalid address range '{}'", target));
+                }
+                Target::Mem {
+                    first: start,
+                    last: end,
+                }
+            }
+        };
+        Ok(Watchpoint {
+            target,
+            read,
+            write,
+            value,
+        })
+    }
+}
+
+/// Breakpoints on `program_counter`, checked before each instruction, and
+/// watchpoints on registers and memory, checked as instructions execute.
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger::default()
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Sets a breakpoint at `addr`, or clears it if there was one. Returns whether it is now set.
+    pub fn toggle_breakpoint(&mut self, addr: u16) -> bool {
+        if self.breakpoints.remove(&addr) {
+            false
+        } else {
+            self.breakpoints.insert(addr);
+            true
+        }
+    }
+
+    /// Returns whether there was a breakpoint at `addr`
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    /// Adds `watchpoint`, or removes it if it was already there. Returns whether it is now set.
+    pub fn toggle_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        if self.remove_watchpoint(watchpoint) {
+            false
+        } else {
+            self.watchpoints.push(watchpoint);
+            true
+        }
+    }
+
+    /// Returns whether `watchpoint` was set
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        let before = self.watchpoints.len();
+        self.watchpoints.retain(|&w| w != watchpoint);
+        self.watchpoints.len() != before
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// Watchpoints in the order they were added
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Breakpoint addresses in ascending order
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// The reason to stop before `chip8` executes its next instruction, if any
+    pub fn check(&self, chip8: &Chip8) -> Option<Stop> {
+        if self.breakpoints.contains(&chip8.program_counter) {
+            return Some(Stop::Breakpoint(chip8.program_counter));
+        }
+        None
+    }
+
+    /// Executes one instruction, returning the watchpoint it triggered if any.
+    ///
+    /// With watchpoints set the instruction runs with `Chip8::log_accesses`
+    /// on, which is left as it was afterwards. Reads are matched against the
+    /// register and memory values before the instruction runs, writes against
+    /// the values it leaves behind.
+    pub fn step(&self, chip8: &mut Chip8) -> Result<Option<Stop>, ExecError> {
+        if self.watchpoints.is_empty() || chip8.exited {
+            return chip8.emulation_cycle().map(|()| None);
+        }
+        let pc = chip8.program_counter;
+        let (vreg, ireg) = (chip8.vreg, chip8.ireg);
+        let logging = chip8.logs_accesses();
+        chip8.log_accesses(true);
+        let result = chip8
+            .emulation_cycle()
+            .map(|()| self.stop_after(chip8, pc, &vreg, ireg));
+        chip8.log_accesses(logging);
+        result
+    }
+
+    /// The watchpoint stop for the instruction at `pc` that just ran, given
+    /// the registers it started with
+    fn stop_after(&self, chip8: &Chip8, pc: u16, vreg: &[u8; 16], ireg: u16) -> Option<Stop> {
+        let before = Values {
+            vreg,
+            ireg,
+            mem: &|addr| chip8.mem_before(addr),
+        };
+        let after = Values {
+            vreg: &chip8.vreg,
+            ireg: chip8.ireg,
+            mem: &|addr| chip8.mem.get(addr as usize).copied(),
+        };
+        let hit = self
+            .triggered(&before, chip8.accesses(), AccessKind::Read)
+            .or_else(|| self.triggered(&after, chip8.accesses(), AccessKind::Write));
+        hit.map(|(watchpoint, kind, target, value)| Stop::Watchpoint {
+            watchpoint,
+            kind,
+            pc,
+            opcode: chip8.opcode,
+            target,
+            value,
+        })
+    }
+
+    fn triggered(
+        &self,
+        values: &Values,
+        accesses: &[Access],
+        kind: AccessKind,
+    ) -> Option<(Watchpoint, AccessKind, Target, u16)> {
+        let watching = |w: &&Watchpoint| match kind {
+            AccessKind::Read => w.read,
+            AccessKind::Write => w.write,
+        };
+        accesses
+            .iter()
+            .filter(|access| access.kind == kind)
+            .find_map(|access| {
+                self.watchpoints.iter().filter(watching).find_map(|w| {
+                    w.matches(values, access.target)
+                        .map(|(target, value)| (*w, kind, target, value))
+                })
+            })
+    }
+}
+
+/// Parses an address written as `0x2A4`, `$2A4` or plain hex `2A4`
+pub fn parse_address(s: &str) -> Result<u16, String> {
+    let s = s.trim();
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .or_else(|| s.strip_prefix('$'))
+        .unwrap_or(s);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address '{}'", s))
+}
+
+/// Watchpoint values are hex with a `0x` prefix, decimal otherwise
+fn parse_value(s: &str) -> Result<u16, String> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("invalid value '{}'", s))
+}
@@ -0,0 +1,282 @@
+//! Plain-language descriptions of the instruction that just ran, for the debug view.
+
+use crate::core::Chip8;
+use crate::instruction::Instruction;
+use crate::platform::Platform;
+
+/// The registers an instruction can change, captured before it runs.
+#[derive(Debug, Clone, Copy)]
+pub struct Before {
+    pc: u16,
+    vreg: [u8; 16],
+    ireg: u16,
+    stack_pointer: u16,
+}
+
+impl Before {
+    pub fn of(chip8: &Chip8) -> Self {
+        Before {
+            pc: chip8.program_counter,
+            vreg: chip8.vreg,
+            ireg: chip8.ireg,
+            stack_pointer: chip8.stack_pointer,
+        }
+    }
+}
+
+impl Chip8 {
+    /// What the instruction that just ran did, with the values it used and produced
+    pub fn describe(&self, before: &Before) -> String {
+        use Instruction::*;
+
+        let instruction = match Instruction::decode(self.opcode) {
+            Some(i) => i,
+            None => return String::from("unknown opcode"),
+        };
+        let v = |x: u8| format!("V{:X} ({:#04X})", x, before.vreg[x as usize]);
+        let vf = self.vreg[0xF];
+        let held = self.program_counter == before.pc;
+        let skip = |condition: String| {
+            if self.program_counter != before.pc.wrapping_add(2) {
+                format!("{}, skip the next instruction", condition)
+            } else {
+                format!("{} is false, no skip", condition)
+            }
+        };
+        let depth = format!(
+            "stack depth {} → {}",
+            before.stack_pointer, self.stack_pointer
+        );
+        let vf_reset = if self.quirks.vf_reset { ", VF=0" } else { "" };
+
+        match instruction {
+            Sys(nnn) => format!(
+                "machine code routine at {:#05X}, called like a subroutine, {}",
+                nnn, depth
+            ),
+            Cls => String::from("clear the display"),
+            Ret => format!("return to {:#05X}, {}", self.program_counter, depth),
+            ScrollDown(n) => format!("scroll the display down {} pixels", n),
+            ScrollUp(n) => format!("scroll the display up {} pixels", n),
+            ScrollRight => String::from("scroll the display right 4 pixels"),
+            ScrollLeft => String::from("scroll the display left 4 pixels"),
+            Exit => String::from("exit the interpreter"),
+            Lores => String::from("switch to 64x32 low resolution"),
+            Hires => String::from("switch to 128x64 high resolution"),
+            Jump(nnn) => format!("jump to {:#05X}", nnn),
+            Call(nnn) => format!(
+                "call {:#05X}, return to {:#05X}, {}",
+                nnn,
+                before.pc.wrapping_add(2),
+                depth
+            ),
+            SkipEqImm(x, nn) => skip(format!("{} == {:#04X}", v(x), nn)),
+            SkipNeImm(x, nn) => skip(format!("{} != {:#04X}", v(x), nn)),
+            SkipEqReg(x, y) => skip(format!("{} == {}", v(x), v(y))),
+            SkipNeReg(x, y) => skip(format!("{} != {}", v(x), v(y))),
+            SaveRange(x, y) => format!(
+                "store {} at {:#05X}: {}",
+                registers(x, y),
+                self.ireg,
+                bytes(&self.vreg, x, y)
+            ),
+            LoadRange(x, y) => format!(
+                "load {} from {:#05X}: {}",
+                registers(x, y),
+                self.ireg,
+                bytes(&self.vreg, x, y)
+            ),
+            LoadImm(x, nn) => format!("V{:X} = {:#04X}", x, nn),
+            AddImm(x, nn) => format!(
+                "{} += {:#04X} → {:#04X}, VF unchanged",
+                v(x),
+                nn,
+                self.vreg[x as usize]
+            ),
+            Move(x, y) => format!("V{:X} = {}", x, v(y)),
+            Or(x, y) => format!(
+                "{} |= {} → {:#04X}{}",
+                v(x),
+                v(y),
+                self.vreg[x as usize],
+                vf_reset
+            ),
+            And(x, y) => format!(
+                "{} &= {} → {:#04X}{}",
+                v(x),
+                v(y),
+                self.vreg[x as usize],
+                vf_reset
+            ),
+            Xor(x, y) => format!(
+                "{} ^= {} → {:#04X}{}",
+                v(x),
+                v(y),
+                self.vreg[x as usize],
+                vf_reset
+            ),
+            AddReg(x, y) => format!(
+                "{} += {} → {:#04X}, VF={} {}",
+                v(x),
+                v(y),
+                self.vreg[x as usize],
+                vf,
+                if vf == 1 { "carry" } else { "no carry" }
+            ),
+            Sub(x, y) => format!(
+                "{} -= {} → {:#04X}, VF={} {}",
+                v(x),
+                v(y),
+                self.vreg[x as usize],
+                vf,
+                if vf == 1 { "no borrow" } else { "borrow" }
+            ),
+            SubReverse(x, y) => format!(
+                "V{:X} = {} - {} → {:#04X}, VF={} {}",
+                x,
+                v(y),
+                v(x),
+                self.vreg[x as usize],
+                vf,
+                if vf == 1 { "no borrow" } else { "borrow" }
+            ),
+            ShiftRight(x, y) | ShiftLeft(x, y) => {
+                let op = if matches!(instruction, ShiftRight(..)) {
+                    ">>"
+                } else {
+                    "<<"
+                };
+                let shifted = if self.quirks.shift_vy {
+                    format!("V{:X} = {} {} 1", x, v(y), op)
+                } else {
+                    format!("{} {}= 1", v(x), op)
+                };
+                format!(
+                    "{} → {:#04X}, VF={} shifted out",
+                    shifted, self.vreg[x as usize], vf
+                )
+            }
+            LoadI(nnn) => format!("I = {:#05X}", nnn),
+            JumpOffset(nnn) => {
+                let reg = if self.quirks.jump_vx {
+                    (nnn >> 8) as u8
+                } else {
+                    0
+                };
+                format!(
+                    "jump to {:#05X} + {} → {:#05X}",
+                    nnn,
+                    v(reg),
+                    self.program_counter
+                )
+            }
+            Random(x, nn) => format!(
+                "V{:X} = random & {:#04X} → {:#04X}",
+                x, nn, self.vreg[x as usize]
+            ),
+            Draw(..) if held => String::from("wait for the vertical blank to draw"),
+            Draw(x, y, n) => {
+                let (width, height) = if n == 0 { (16, 16) } else { (8, n) };
+                let planes = if self.platform == Platform::XoChip {
+                    format!(" on planes {:#04b}", self.gfx.planes)
+                } else {
+                    String::new()
+                };
+                format!(
+                    "draw {}x{} sprite from I ({:#05X}) at {}, {}{}, VF={} {}",
+                    width,
+                    height,
+                    before.ireg,
+                    v(x),
+                    v(y),
+                    planes,
+                    vf,
+                    if vf == 1 { "collision" } else { "no collision" }
+                )
+            }
+            SkipKey(x) => skip(format!("key {} is pressed", v(x))),
+            SkipNotKey(x) => skip(format!("key {} is not pressed", v(x))),
+            LoadILong => format!("I = {:#06X}", self.ireg),
+            Plane(n) => format!("select planes {:#04b} for drawing", n),
+            Audio => format!(
+                "load the audio pattern from {:#05X}-{:#05X}",
+                self.ireg,
+                self.ireg.wrapping_add(15)
+            ),
+            GetDelay(x) => format!("V{:X} = delay timer → {:#04X}", x, self.vreg[x as usize]),
+            WaitKey(x) if held => format!("wait for a key press to store in V{:X}", x),
+            WaitKey(x) => format!("V{:X} = pressed key {:X}", x, self.vreg[x as usize]),
+            SetDelay(x) => format!("delay timer = {}", v(x)),
+            SetSound(x) => format!("sound timer = {}", v(x)),
+            Pitch(x) => format!("audio pitch = {}", v(x)),
+            AddI(x) => format!("I ({:#05X}) += {} → {:#05X}", before.ireg, v(x), self.ireg),
+            Font(x) => format!("I = font sprite for digit {} → {:#05X}", v(x), self.ireg),
+            BigFont(x) => format!(
+                "I = big font sprite for digit {} → {:#05X}",
+                v(x),
+                self.ireg
+            ),
+            Bcd(x) => {
+                let addr = before.ireg as usize;
+                format!(
+                    "store the decimal digits of {} = {} at {:#05X}: {}, {}, {}",
+                    v(x),
+                    before.vreg[x as usize],
+                    addr,
+                    self.mem[addr],
+                    self.mem[addr + 1],
+                    self.mem[addr + 2]
+                )
+            }
+            Store(x) => format!(
+                "store {} at {:#05X}: {}{}",
+                registers(0, x),
+                before.ireg,
+                bytes(&before.vreg, 0, x),
+                self.ireg_change(before)
+            ),
+            Load(x) => format!(
+                "load {} from {:#05X}: {}{}",
+                registers(0, x),
+                before.ireg,
+                bytes(&self.vreg, 0, x),
+                self.ireg_change(before)
+            ),
+            StoreRpl(x) => format!(
+                "save {} to the RPL flags: {}",
+                registers(0, x),
+                bytes(&before.vreg, 0, x)
+            ),
+            LoadRpl(x) => format!(
+                "load {} from the RPL flags: {}",
+                registers(0, x),
+                bytes(&self.vreg, 0, x)
+            ),
+        }
+    }
+
+    /// How FX55 and FX65 moved I, depending on the load/store quirk
+    fn ireg_change(&self, before: &Before) -> String {
+        if self.ireg == before.ireg {
+            String::from(", I unchanged")
+        } else {
+            format!(", I → {:#05X}", self.ireg)
+        }
+    }
+}
+
+fn registers(x: u8, y: u8) -> String {
+    if x == y {
+        format!("V{:X}", x)
+    } else {
+        format!("V{:X}-V{:X}", x, y)
+    }
+}
+
+/// Register values from VX to VY, in that order
+fn bytes(vreg: &[u8; 16], x: u8, y: u8) -> String {
+    let values: Vec<String> = Chip8::register_range(x, y)
+        .map(|reg| format!("{:#04X}", vreg[reg as usize]))
+        .collect();
+    values.join(", ")
+}
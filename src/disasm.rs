@@ -0,0 +1,356 @@
+//! Disassembler that follows control flow from the entry point.
+//!
+//! Everything reachable through jumps, calls and skips is printed as code,
+//! the rest as data. Jump, call and `LD I` targets get generated labels, and
+//! data drawn by a `DRW` right after the `LD I` that points at it is printed
+//! as a sprite bitmap. Labels are numbered in the order control flow reaches
+//! them rather than named after their address, and `without_addresses` drops
+//! the address comments, so two ROM revisions can be compared with a plain
+//! text diff.
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::instruction::Instruction;
+use crate::platform::Platform;
+
+/// Where ROMs are loaded and start executing
+pub const ORIGIN: u16 = 0x200;
+
+/// Bytes per `db` line in byte tables
+const TABLE_WIDTH: usize = 8;
+
+/// What a label points at, in order of precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Sprite,
+    Code,
+    Subroutine,
+}
+
+/// A sprite found in the data, drawn `bytes_per_row` bytes wide
+#[derive(Debug, Clone, Copy)]
+struct Sprite {
+    bytes_per_row: usize,
+    len: usize,
+}
+
+/// The result of analysing a ROM, printed with `Display`.
+#[derive(Debug, Clone)]
+pub struct Disassembly {
+    rom: Vec<u8>,
+    platform: Platform,
+    /// Instruction addresses and their length in bytes
+    code: BTreeMap<u16, u16>,
+    labels: BTreeMap<u16, LabelKind>,
+    /// Label addresses in the order tracing found them
+    found: Vec<u16>,
+    /// Names of the labels that don't fall inside an instruction
+    names: BTreeMap<u16, String>,
+    sprites: BTreeMap<u16, Sprite>,
+    /// Print addresses and raw bytes in comments
+    addresses: bool,
+}
+
+pub fn disassemble(rom: &[u8], platform: Platform) -> Disassembly {
+    let mut disassembly = Disassembly {
+        rom: rom.to_vec(),
+        platform,
+        code: BTreeMap::new(),
+        labels: BTreeMap::new(),
+        found: Vec::new(),
+        names: BTreeMap::new(),
+        sprites: BTreeMap::new(),
+        addresses: true,
+    };
+    disassembly.trace(ORIGIN);
+    disassembly.name_labels();
+    disassembly
+}
+
+impl Disassembly {
+    /// Leaves out the address and raw byte comments, sprite pixels are still shown
+    pub fn without_addresses(mut self) -> Self {
+        self.addresses = false;
+        self
+    }
+
+    /// Generated label names by address
+    pub fn labels(&self) -> impl Iterator<Item = (u16, &str)> + '_ {
+        self.names.iter().map(|(&addr, name)| (addr, name.as_str()))
+    }
+
+    fn end(&self) -> usize {
+        ORIGIN as usize + self.rom.len()
+    }
+
+    fn in_rom(&self, addr: u16) -> bool {
+        addr >= ORIGIN && (addr as usize) < self.end()
+    }
+
+    fn byte(&self, addr: usize) -> Option<u8> {
+        self.rom.get(addr.checked_sub(ORIGIN as usize)?).copied()
+    }
+
+    fn word(&self, addr: usize) -> Option<u16> {
+        Some((self.byte(addr)? as u16) << 8 | self.byte(addr + 1)? as u16)
+    }
+
+    /// The instruction at `addr` and its length, if it is one this platform runs
+    fn decode(&self, addr: u16) -> Option<(Instruction, u16)> {
+        let instruction = Instruction::decode(self.word(addr as usize)?)?;
+        if instruction.is_xochip() && self.platform != Platform::XoChip {
+            return None;
+        }
+        if instruction == Instruction::LoadILong {
+            self.word(addr as usize + 2)?;
+            return Some((instruction, 4));
+        }
+        Some((instruction, 2))
+    }
+
+    /// Length of the instruction a skip at `addr - 2` jumps over
+    fn skipped_len(&self, addr: u16) -> u16 {
+        match self.decode(addr) {
+            Some((Instruction::LoadILong, len)) if self.platform == Platform::XoChip => len,
+            _ => 2,
+        }
+    }
+
+    fn label(&mut self, addr: u16, kind: LabelKind) {
+        if self.in_rom(addr) {
+            let found = &mut self.found;
+            let label = self.labels.entry(addr).or_insert_with(|| {
+                found.push(addr);
+                kind
+            });
+            *label = (*label).max(kind);
+        }
+    }
+
+    /// Marks everything reachable from `entry` as code
+    fn trace(&mut self, entry: u16) {
+        use Instruction::*;
+
+        let mut work = vec![entry];
+        while let Some(mut addr) = work.pop() {
+            // Target of the last LD I in this block, for spotting sprites
+            let mut sprite_addr = None;
+            while !self.code.contains_key(&addr) {
+                let (instruction, len) = match self.decode(addr) {
+                    Some(decoded) => decoded,
+                    None => break,
+                };
+                self.code.insert(addr, len);
+                let next = addr.wrapping_add(len);
+                match instruction {
+                    Jump(target) => {
+                        self.label(target, LabelKind::Code);
+                        work.push(target);
+                        break;
+                    }
+                    Call(target) => {
+                        self.label(target, LabelKind::Subroutine);
+                        work.push(target);
+                    }
+                    // Computed jumps can't be followed
+                    Ret | Exit | JumpOffset(_) => break,
+                    SkipEqImm(..) | SkipNeImm(..) | SkipEqReg(..) | SkipNeReg(..) | SkipKey(_)
+                    | SkipNotKey(_) => {
+                        work.push(next.wrapping_add(self.skipped_len(next)));
+                    }
+                    LoadI(target) => {
+                        self.label(target, LabelKind::Data);
+                        sprite_addr = Some(target);
+                    }
+                    LoadILong => {
+                        let target = self.word(addr as usize + 2).unwrap_or(0);
+                        self.label(target, LabelKind::Data);
+                        sprite_addr = Some(target);
+                    }
+                    Draw(_, _, n) => {
+                        if let Some(target) = sprite_addr.filter(|&a| self.in_rom(a)) {
+                            self.label(target, LabelKind::Sprite);
+                            let sprite = if n == 0 {
+                                Sprite {
+                                    bytes_per_row: 2,
+                                    len: 32,
+                                }
+                            } else {
+                                Sprite {
+                                    bytes_per_row: 1,
+                                    len: n as usize,
+                                }
+                            };
+                            let known = self.sprites.entry(target).or_insert(sprite);
+                            if sprite.len > known.len {
+                                *known = sprite;
+                            }
+                        }
+                    }
+                    AddI(_) | Font(_) | BigFont(_) | Store(_) | Load(_) => sprite_addr = None,
+                    _ => {}
+                }
+                addr = next;
+            }
+        }
+    }
+
+    /// Numbers the labels of each kind in the order they were found, leaving
+    /// out those in the middle of an instruction
+    fn name_labels(&mut self) {
+        let mut counts = BTreeMap::new();
+        for &addr in &self.found {
+            let inside = match self.code.range(..addr).next_back() {
+                Some((&start, &len)) => start as usize + len as usize > addr as usize,
+                None => false,
+            };
+            if inside {
+                continue;
+            }
+            let kind = self.labels[&addr];
+            let count = counts.entry(kind).or_insert(0);
+            *count += 1;
+            self.names.insert(addr, label_name(kind, *count));
+        }
+    }
+
+    /// The label printed at `addr`
+    fn label_at(&self, addr: u16) -> Option<&str> {
+        self.names.get(&addr).map(String::as_str)
+    }
+
+    /// Operands that are addresses are replaced by their label
+    fn format_instruction(&self, addr: u16, instruction: Instruction) -> String {
+        use Instruction::*;
+
+        let target = |target: u16| match self.label_at(target) {
+            Some(label) => label.to_string(),
+            None => format!("{:#05X}", target),
+        };
+        match instruction {
+            Sys(nnn) => format!("SYS {}", target(nnn)),
+            Jump(nnn) => format!("JP {}", target(nnn)),
+            Call(nnn) => format!("CALL {}", target(nnn)),
+            LoadI(nnn) => format!("LD I, {}", target(nnn)),
+            JumpOffset(nnn) => format!("JP V0, {}", target(nnn)),
+            LoadILong => {
+                let nnnn = self.word(addr as usize + 2).unwrap_or(0);
+                match self.label_at(nnnn) {
+                    Some(label) => format!("LD I, LONG {}", label),
+                    None => format!("LD I, LONG {:#06X}", nnnn),
+                }
+            }
+            _ => instruction.to_string(),
+        }
+    }
+
+    /// First address after `addr` where a label or an instruction starts
+    fn data_end(&self, addr: usize) -> usize {
+        let next_label = self
+            .labels
+            .range(addr as u16..)
+            .map(|(&a, _)| a as usize)
+            .find(|&a| a > addr);
+        let next_code = self
+            .code
+            .range(addr as u16..)
+            .next()
+            .map(|(&a, _)| a as usize);
+        [next_label, next_code]
+            .iter()
+            .flatten()
+            .copied()
+            .fold(self.end(), usize::min)
+    }
+}
+
+/// The name of the `n`th label of `kind`, counting from 1
+fn label_name(kind: LabelKind, n: usize) -> String {
+    match kind {
+        LabelKind::Code => format!("label_{}", n),
+        LabelKind::Subroutine => format!("sub_{}", n),
+        LabelKind::Data => format!("data_{}", n),
+        LabelKind::Sprite => format!("sprite_{}", n),
+    }
+}
+
+/// A line of output with a trailing comment, if there is one
+fn line(f: &mut fmt::Formatter<'_>, text: &str, comment: &str) -> fmt::Result {
+    if comment.is_empty() {
+        writeln!(f, "    {}", text)
+    } else {
+        writeln!(f, "    {:<28}; {}", text, comment)
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "; {} bytes, platform {}, loaded at {:#05X}",
+            self.rom.len(),
+            self.platform,
+            ORIGIN
+        )?;
+        let mut addr = ORIGIN as usize;
+        while addr < self.end() {
+            if let Some(label) = self.label_at(addr as u16) {
+                writeln!(f)?;
+                writeln!(f, "{}:", label)?;
+            }
+            if let Some(&len) = self.code.get(&(addr as u16)) {
+                let (instruction, _) = self.decode(addr as u16).unwrap();
+                let comment = if self.addresses {
+                    let bytes = &self.rom[addr - ORIGIN as usize..][..len as usize];
+                    let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                    format!("{:03X}: {}", addr, hex)
+                } else {
+                    String::new()
+                };
+                line(
+                    f,
+                    &self.format_instruction(addr as u16, instruction),
+                    &comment,
+                )?;
+                addr += len as usize;
+                continue;
+            }
+
+            let end = self.data_end(addr);
+            let data = &self.rom[addr - ORIGIN as usize..end - ORIGIN as usize];
+            let sprite_len = match self.sprites.get(&(addr as u16)) {
+                Some(sprite) => {
+                    let len = sprite.len.min(data.len());
+                    for (row, bytes) in data[..len].chunks(sprite.bytes_per_row).enumerate() {
+                        let values: Vec<String> =
+                            bytes.iter().map(|b| format!("{:#010b}", b)).collect();
+                        let pixels: String = bytes
+                            .iter()
+                            .map(|b| format!("{:08b}", b).replace('0', ".").replace('1', "#"))
+                            .collect();
+                        let comment = if self.addresses {
+                            format!("{:03X}: {}", addr + row * sprite.bytes_per_row, pixels)
+                        } else {
+                            pixels
+                        };
+                        line(f, &format!("db {}", values.join(", ")), &comment)?;
+                    }
+                    len
+                }
+                None => 0,
+            };
+            for (row, bytes) in data[sprite_len..].chunks(TABLE_WIDTH).enumerate() {
+                let values: Vec<String> = bytes.iter().map(|b| format!("{:#04X}", b)).collect();
+                let comment = if self.addresses {
+                    format!("{:03X}", addr + sprite_len + row * TABLE_WIDTH)
+                } else {
+                    String::new()
+                };
+                line(f, &format!("db {}", values.join(", ")), &comment)?;
+            }
+            addr = end;
+        }
+        Ok(())
+    }
+}
@@ -1,65 +1,126 @@
-use tui::{style::Color, widgets::canvas::Shape};
+/// Width of the SUPER-CHIP hi-res screen, the grid is always allocated at this size.
+pub const HIRES_WIDTH: usize = 128;
+/// Height of the SUPER-CHIP hi-res screen.
+pub const HIRES_HEIGHT: usize = 64;
 
+/// Frontend agnostic framebuffer.
 #[derive(Debug, Clone)]
 pub struct Display {
-    pub grid: [[u8; 32]; 64],
+    /// Each cell holds one bit per bitplane, plain CHIP-8 only ever uses bit 0
+    pub grid: [[u8; HIRES_HEIGHT]; HIRES_WIDTH],
+    /// 128x64 mode, only the top left 64x32 of the grid is used otherwise
+    pub hires: bool,
+    /// Bitplanes affected by drawing, clearing and scrolling (XO-CHIP FN01)
+    pub planes: u8,
 }
 
-impl Shape for Display {
-    fn draw(&self, painter: &mut tui::widgets::canvas::Painter) {
-        let max_y = 32;
-        let max_x = 64;
-        for y in 0..max_y {
-            for x in 0..max_x {
-                let pixel = self.grid[x][y];
-                if pixel == 1 {
-                    let (x, y) = painter.get_point(x as f64, (31 - y) as f64).unwrap();
-                    painter.paint(x, y, Color::Reset)
-                }
-            }
-        }
+impl Default for Display {
+    fn default() -> Self {
+        Display::new()
     }
 }
 
 impl Display {
+    pub fn new() -> Self {
+        Display {
+            grid: [[0u8; HIRES_HEIGHT]; HIRES_WIDTH],
+            hires: false,
+            planes: 1,
+        }
+    }
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            64
+        }
+    }
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            32
+        }
+    }
+    /// Bitplanes lit at (x, y), 0 is off
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.grid[x][y]
+    }
+    /// Clears the selected bitplanes
     pub fn cls(&mut self) {
-        self.grid.fill([0; 32]);
+        let planes = self.planes;
+        for column in self.grid.iter_mut() {
+            for pixel in column.iter_mut() {
+                *pixel &= !planes;
+            }
+        }
+    }
+    /// Switches between 64x32 and 128x64, clearing every plane like most interpreters do
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.grid.fill([0; HIRES_HEIGHT]);
+    }
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.scroll(0, rows as isize);
+    }
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.scroll(0, -(rows as isize));
+    }
+    pub fn scroll_right(&mut self, columns: usize) {
+        self.scroll(columns as isize, 0);
+    }
+    pub fn scroll_left(&mut self, columns: usize) {
+        self.scroll(-(columns as isize), 0);
+    }
+    /// Moves the selected bitplanes by (dx, dy), pixels shifted in from the edges are blank
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let old = self.grid;
+        for x in 0..width {
+            for y in 0..height {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let moved = if (0..width).contains(&src_x) && (0..height).contains(&src_y) {
+                    old[src_x as usize][src_y as usize]
+                } else {
+                    0
+                };
+                let pixel = &mut self.grid[x as usize][y as usize];
+                *pixel = (*pixel & !self.planes) | (moved & self.planes);
+            }
+        }
     }
     pub fn draw_sprite(
         &mut self,
         sprite_start_x: u8,
         sprite_start_y: u8,
         sprite: Vec<Vec<u8>>,
+        clip: bool,
+        plane: u8,
     ) -> bool {
-        // let mut output = std::fs::File::create("spritedbg").unwrap();
-        let max_y = sprite[0].len() - 1;
-        let max_x = sprite.len() - 1;
+        let (width, height) = (self.width(), self.height());
         let mut collision = false;
-        let sprite_start_x = sprite_start_x % 63;
-        let sprite_start_y = sprite_start_y % 31;
-        for y in 0..=max_y {
-            for x in 0..=max_x {
-                // write!(output, "{}", sprite[x][y]).unwrap();
-                let x_coord = sprite_start_x as usize + x;
-                let y_coord = sprite_start_y as usize + y;
-                if !(x_coord < 64 && y_coord < 32) {
+        // The starting position always wraps, only the pixels past the edge are clipped
+        let sprite_start_x = sprite_start_x as usize % width;
+        let sprite_start_y = sprite_start_y as usize % height;
+        for (x, column) in sprite.iter().enumerate() {
+            for (y, &bit) in column.iter().enumerate() {
+                let mut x_coord = sprite_start_x + x;
+                let mut y_coord = sprite_start_y + y;
+                if clip && !(x_coord < width && y_coord < height) {
+                    continue;
+                }
+                x_coord %= width;
+                y_coord %= height;
+                if bit == 0 {
                     continue;
                 }
-                let pixel = self.grid[x_coord][y_coord];
-                if pixel == 1 && sprite[x][y] == 1 {
+                let pixel = &mut self.grid[x_coord][y_coord];
+                if *pixel & plane != 0 {
                     collision = true;
                 }
-                self.grid[x_coord][y_coord] ^= sprite[x][y];
+                *pixel ^= plane;
             }
-            // writeln!(output).unwrap();
         }
-        // writeln!(
-        //     output,
-        //     "Sprite location - x:{} y:{}",
-        //     sprite_start_x, sprite_start_y
-        // )
-        // .unwrap();
-        // writeln!(output, "Sprite max - x:{} y:{}", max_x, max_y).unwrap();
         collision
     }
 }
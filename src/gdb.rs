@@ -0,0 +1,379 @@
+//! GDB remote serial protocol, enough to inspect and drive the emulator.
+//!
+//! Registers are V0-VF, I, PC, SP, DT and ST in that order, described to the
+//! debugger through `target.xml`. Memory is `Chip8::mem`. Software
+//! breakpoints and write, read and access watchpoints map onto `Debugger`.
+//! Stepping and continuing are left to the caller, which owns the frame loop.
+
+use std::{
+    io::{self, BufRead, Write},
+    ops::Range,
+};
+
+use crate::access::{AccessKind, Target};
+use crate::core::{Chip8, ExecError};
+use crate::debugger::{Debugger, Stop, Watchpoint};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chipterm.chip8">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="16" type="uint16"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// Register numbers after V0-VF
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+const REG_COUNT: usize = 21;
+
+/// What arrived from the debugger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    /// The contents of a `$...#xx` packet
+    Command(String),
+    /// Ctrl-C, sent outside of any packet
+    Interrupt,
+}
+
+/// Reads the next packet, acknowledging it on `ack`. `None` once the
+/// connection is closed.
+pub fn read_packet(input: &mut impl BufRead, ack: &mut impl Write) -> io::Result<Option<Packet>> {
+    loop {
+        let mut byte = [0];
+        if input.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        match byte[0] {
+            0x03 => return Ok(Some(Packet::Interrupt)),
+            b'$' => {}
+            // Acks of our own packets and line noise
+            _ => continue,
+        }
+        let mut data = Vec::new();
+        if input.read_until(b'#', &mut data)? == 0 || data.pop() != Some(b'#') {
+            return Ok(None);
+        }
+        let mut checksum = [0; 2];
+        input.read_exact(&mut checksum)?;
+        let expected = std::str::from_utf8(&checksum)
+            .ok()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        if expected != Some(sum(&data)) {
+            ack.write_all(b"-")?;
+            ack.flush()?;
+            continue;
+        }
+        ack.write_all(b"+")?;
+        ack.flush()?;
+        return Ok(Some(Packet::Command(unescape(&data))));
+    }
+}
+
+/// Sends `data` as a `$...#xx` packet
+pub fn write_packet(out: &mut impl Write, data: &str) -> io::Result<()> {
+    let mut packet = Vec::with_capacity(data.len() + 4);
+    packet.push(b'$');
+    for &byte in data.as_bytes() {
+        if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+            packet.extend_from_slice(&[b'}', byte ^ 0x20]);
+        } else {
+            packet.push(byte);
+        }
+    }
+    let checksum = sum(&packet[1..]);
+    packet.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+    out.write_all(&packet)?;
+    out.flush()
+}
+
+fn sum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn unescape(data: &[u8]) -> String {
+    let mut bytes = Vec::with_capacity(data.len());
+    let mut escaped = false;
+    for &byte in data {
+        if escaped {
+            bytes.push(byte ^ 0x20);
+            escaped = false;
+        } else if byte == b'}' {
+            escaped = true;
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// What the caller has to do about a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    /// Send this packet back
+    Packet(String),
+    /// Execute one instruction, then send `stop_reply`
+    Step,
+    /// Run until something stops the emulator, then send `stop_reply`
+    Continue,
+    /// Send OK and let the emulator run on its own
+    Detach,
+    Kill,
+}
+
+/// Answers a command that doesn't need the emulator to run
+pub fn handle(command: &str, chip8: &mut Chip8, debugger: &mut Debugger) -> Reply {
+    let reply = |s: &str| Reply::Packet(s.to_owned());
+    let (kind, args) = command.split_at(command.chars().next().map_or(0, char::len_utf8));
+    match kind {
+        "?" => reply("S05"),
+        "g" => Reply::Packet((0..REG_COUNT).map(|n| read_register(chip8, n)).collect()),
+        "G" => {
+            let mut rest = args;
+            for n in 0..REG_COUNT {
+                let width = register_width(n) * 2;
+                if rest.len() < width {
+                    break;
+                }
+                let (value, tail) = rest.split_at(width);
+                if !write_register(chip8, n, value) {
+                    return reply("E01");
+                }
+                rest = tail;
+            }
+            reply("OK")
+        }
+        "p" => match usize::from_str_radix(args, 16) {
+            Ok(n) if n < REG_COUNT => Reply::Packet(read_register(chip8, n)),
+            _ => reply("E01"),
+        },
+        "P" => match args.split_once('=') {
+            Some((n, value)) => match usize::from_str_radix(n, 16) {
+                Ok(n) if n < REG_COUNT && write_register(chip8, n, value) => reply("OK"),
+                _ => reply("E01"),
+            },
+            None => reply("E01"),
+        },
+        "m" => match parse_range(args) {
+            Some(range) => match chip8.mem.get(range) {
+                Some(bytes) => Reply::Packet(hex(bytes)),
+                None => reply("E01"),
+            },
+            None => reply("E01"),
+        },
+        "M" => {
+            let (range, data) = args.split_once(':').unwrap_or((args, ""));
+            match (parse_range(range), unhex(data)) {
+                (Some(range), Some(bytes)) if bytes.len() == range.len() => {
+                    match chip8.mem.get_mut(range.clone()) {
+                        Some(mem) => {
+                            mem.copy_from_slice(&bytes);
+                            chip8.mark_dirty(range);
+                            reply("OK")
+                        }
+                        None => reply("E01"),
+                    }
+                }
+                _ => reply("E01"),
+            }
+        }
+        "Z" | "z" => breakpoint(kind == "Z", args, debugger),
+        "s" if args.is_empty() => Reply::Step,
+        "c" if args.is_empty() => Reply::Continue,
+        "D" => Reply::Detach,
+        "k" => Reply::Kill,
+        // There is one thread and it is always selected
+        "H" | "T" => reply("OK"),
+        "q" => query(args),
+        _ => reply(""),
+    }
+}
+
+fn query(args: &str) -> Reply {
+    let reply = |s: &str| Reply::Packet(s.to_owned());
+    if args.starts_with("Supported") {
+        return reply("PacketSize=1000;qXfer:features:read+;swbreak+;hwbreak+");
+    }
+    if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+        return match parse_range(range) {
+            Some(range) => {
+                let xml = TARGET_XML.as_bytes();
+                let start = range.start.min(xml.len());
+                let end = range.end.min(xml.len());
+                let more = if end < xml.len() { "m" } else { "l" };
+                Reply::Packet(format!(
+                    "{}{}",
+                    more,
+                    String::from_utf8_lossy(&xml[start..end])
+                ))
+            }
+            None => reply("E01"),
+        };
+    }
+    match args {
+        "Attached" => reply("1"),
+        "C" => reply("QC1"),
+        "fThreadInfo" => reply("m1"),
+        "sThreadInfo" => reply("l"),
+        _ => reply(""),
+    }
+}
+
+/// `Z0`/`z0` sets or clears a breakpoint, `Z2`-`Z4` write, read and access watchpoints
+fn breakpoint(insert: bool, args: &str, debugger: &mut Debugger) -> Reply {
+    let mut fields = args.split(',');
+    let (kind, addr, len) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(kind), Some(addr), Some(len)) => (kind, addr, len),
+        _ => return Reply::Packet(String::from("E01")),
+    };
+    let (addr, len) = match (u16::from_str_radix(addr, 16), u16::from_str_radix(len, 16)) {
+        (Ok(addr), Ok(len)) => (addr, len),
+        _ => return Reply::Packet(String::from("E01")),
+    };
+    let (read, write) = match kind {
+        "0" | "1" => {
+            if insert {
+                debugger.add_breakpoint(addr);
+            } else {
+                debugger.remove_breakpoint(addr);
+            }
+            return Reply::Packet(String::from("OK"));
+        }
+        "2" => (false, true),
+        "3" => (true, false),
+        "4" => (true, true),
+        _ => return Reply::Packet(String::new()),
+    };
+    let last = match addr.checked_add(len.max(1) - 1) {
+        Some(last) => last,
+        None => return Reply::Packet(String::from("E01")),
+    };
+    let watchpoint = Watchpoint {
+        target: Target::Mem { first: addr, last },
+        read,
+        write,
+        value: None,
+    };
+    if insert {
+        debugger.add_watchpoint(watchpoint);
+    } else {
+        debugger.remove_watchpoint(watchpoint);
+    }
+    Reply::Packet(String::from("OK"))
+}
+
+/// The packet telling the debugger why the emulator stopped
+pub fn stop_reply(stop: Option<&Stop>, error: Option<&ExecError>, exited: bool) -> String {
+    if exited {
+        return String::from("W00");
+    }
+    match (error, stop) {
+        (Some(ExecError::UnknownOpcode { .. }), _) => String::from("S04"),
+        (Some(_), _) => String::from("S0b"),
+        (None, Some(Stop::Breakpoint(_))) => String::from("T05swbreak:;"),
+        (
+            None,
+            Some(Stop::Watchpoint {
+                kind,
+                target: Target::Mem { first: addr, .. },
+                watchpoint,
+                ..
+            }),
+        ) => {
+            let name = match (kind, watchpoint.read && watchpoint.write) {
+                (_, true) => "awatch",
+                (AccessKind::Read, false) => "rwatch",
+                (AccessKind::Write, false) => "watch",
+            };
+            format!("T05{}:{:x};", name, addr)
+        }
+        (None, _) => String::from("S05"),
+    }
+}
+
+/// Bytes per register in `g` packets
+fn register_width(n: usize) -> usize {
+    match n {
+        REG_I | REG_PC | REG_SP => 2,
+        _ => 1,
+    }
+}
+
+/// A register as little-endian hex
+fn read_register(chip8: &Chip8, n: usize) -> String {
+    match n {
+        0..=15 => format!("{:02x}", chip8.vreg[n]),
+        REG_I => hex(&chip8.ireg.to_le_bytes()),
+        REG_PC => hex(&chip8.program_counter.to_le_bytes()),
+        REG_SP => hex(&chip8.stack_pointer.to_le_bytes()),
+        REG_DT => format!("{:02x}", chip8.delay_timer),
+        REG_ST => format!("{:02x}", chip8.sound_timer),
+        _ => String::new(),
+    }
+}
+
+/// Sets a register from little-endian hex, false if the value is malformed
+fn write_register(chip8: &mut Chip8, n: usize, value: &str) -> bool {
+    let bytes = match unhex(value) {
+        Some(bytes) if bytes.len() == register_width(n) => bytes,
+        _ => return false,
+    };
+    let word = || u16::from_le_bytes([bytes[0], bytes[1]]);
+    match n {
+        0..=15 => chip8.vreg[n] = bytes[0],
+        REG_I => chip8.ireg = word(),
+        REG_PC => chip8.program_counter = word(),
+        // Past 16 the next CALL or RET would index out of the stack
+        REG_SP if word() as usize <= chip8.stack.len() => chip8.stack_pointer = word(),
+        REG_DT => chip8.delay_timer = bytes[0],
+        REG_ST => chip8.sound_timer = bytes[0],
+        _ => return false,
+    }
+    true
+}
+
+/// `addr,len` in hex
+fn parse_range(s: &str) -> Option<Range<usize>> {
+    let (addr, len) = s.split_once(',')?;
+    let addr = usize::from_str_radix(addr, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    Some(addr..addr.checked_add(len)?)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
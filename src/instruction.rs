@@ -0,0 +1,389 @@
+use std::fmt;
+
+/// A decoded CHIP-8, SUPER-CHIP or XO-CHIP instruction.
+///
+/// Register operands are register numbers (0x0-0xF), not their values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// 0NNN - call machine code routine at NNN
+    Sys(u16),
+    /// 00E0 - clear the selected planes
+    Cls,
+    /// 00EE - return from subroutine
+    Ret,
+    /// 00CN - scroll down N pixels (SUPER-CHIP)
+    ScrollDown(u8),
+    /// 00DN - scroll up N pixels (XO-CHIP)
+    ScrollUp(u8),
+    /// 00FB - scroll right 4 pixels (SUPER-CHIP)
+    ScrollRight,
+    /// 00FC - scroll left 4 pixels (SUPER-CHIP)
+    ScrollLeft,
+    /// 00FD - exit the interpreter (SUPER-CHIP)
+    Exit,
+    /// 00FE - 64x32 mode (SUPER-CHIP)
+    Lores,
+    /// 00FF - 128x64 mode (SUPER-CHIP)
+    Hires,
+    /// 1NNN - jump to NNN
+    Jump(u16),
+    /// 2NNN - call subroutine at NNN
+    Call(u16),
+    /// 3XNN - skip if VX == NN
+    SkipEqImm(u8, u8),
+    /// 4XNN - skip if VX != NN
+    SkipNeImm(u8, u8),
+    /// 5XY0 - skip if VX == VY
+    SkipEqReg(u8, u8),
+    /// 5XY2 - store VX..VY at I (XO-CHIP)
+    SaveRange(u8, u8),
+    /// 5XY3 - load VX..VY from I (XO-CHIP)
+    LoadRange(u8, u8),
+    /// 6XNN - VX = NN
+    LoadImm(u8, u8),
+    /// 7XNN - VX += NN without carry
+    AddImm(u8, u8),
+    /// 8XY0 - VX = VY
+    Move(u8, u8),
+    /// 8XY1 - VX |= VY
+    Or(u8, u8),
+    /// 8XY2 - VX &= VY
+    And(u8, u8),
+    /// 8XY3 - VX ^= VY
+    Xor(u8, u8),
+    /// 8XY4 - VX += VY, VF = carry
+    AddReg(u8, u8),
+    /// 8XY5 - VX -= VY, VF = not borrow
+    Sub(u8, u8),
+    /// 8XY6 - VX >>= 1, VF = shifted out bit
+    ShiftRight(u8, u8),
+    /// 8XY7 - VX = VY - VX, VF = not borrow
+    SubReverse(u8, u8),
+    /// 8XYE - VX <<= 1, VF = shifted out bit
+    ShiftLeft(u8, u8),
+    /// 9XY0 - skip if VX != VY
+    SkipNeReg(u8, u8),
+    /// ANNN - I = NNN
+    LoadI(u16),
+    /// BNNN - jump to NNN + V0
+    JumpOffset(u16),
+    /// CXNN - VX = random & NN
+    Random(u8, u8),
+    /// DXYN - draw N rows sprite at (VX, VY), 16x16 when N is 0
+    Draw(u8, u8, u8),
+    /// EX9E - skip if key VX is pressed
+    SkipKey(u8),
+    /// EXA1 - skip if key VX is not pressed
+    SkipNotKey(u8),
+    /// F000 - I = the 16-bit word following the instruction (XO-CHIP)
+    LoadILong,
+    /// FN01 - select bitplanes N (XO-CHIP)
+    Plane(u8),
+    /// F002 - load the audio pattern from I (XO-CHIP)
+    Audio,
+    /// FX07 - VX = delay timer
+    GetDelay(u8),
+    /// FX0A - wait for a key press and store it in VX
+    WaitKey(u8),
+    /// FX15 - delay timer = VX
+    SetDelay(u8),
+    /// FX18 - sound timer = VX
+    SetSound(u8),
+    /// FX1E - I += VX
+    AddI(u8),
+    /// FX29 - I = small font sprite for VX
+    Font(u8),
+    /// FX30 - I = big font sprite for VX (SUPER-CHIP)
+    BigFont(u8),
+    /// FX33 - BCD of VX at I, I+1, I+2
+    Bcd(u8),
+    /// FX3A - audio pitch = VX (XO-CHIP)
+    Pitch(u8),
+    /// FX55 - store V0..VX at I
+    Store(u8),
+    /// FX65 - load V0..VX from I
+    Load(u8),
+    /// FX75 - store V0..VX in RPL flags (SUPER-CHIP)
+    StoreRpl(u8),
+    /// FX85 - load V0..VX from RPL flags (SUPER-CHIP)
+    LoadRpl(u8),
+}
+
+impl Instruction {
+    /// Decodes a 16-bit opcode, `None` for encodings no supported platform defines
+    pub fn decode(opcode: u16) -> Option<Instruction> {
+        use Instruction::*;
+
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+        let instruction = match opcode >> 12 {
+            0x0 => match opcode {
+                0x00E0 => Cls,
+                0x00EE => Ret,
+                0x00C0..=0x00CF => ScrollDown(n),
+                0x00D0..=0x00DF => ScrollUp(n),
+                0x00FB => ScrollRight,
+                0x00FC => ScrollLeft,
+                0x00FD => Exit,
+                0x00FE => Lores,
+                0x00FF => Hires,
+                _ => Sys(nnn),
+            },
+            0x1 => Jump(nnn),
+            0x2 => Call(nnn),
+            0x3 => SkipEqImm(x, nn),
+            0x4 => SkipNeImm(x, nn),
+            0x5 => match n {
+                0x0 => SkipEqReg(x, y),
+                0x2 => SaveRange(x, y),
+                0x3 => LoadRange(x, y),
+                _ => return None,
+            },
+            0x6 => LoadImm(x, nn),
+            0x7 => AddImm(x, nn),
+            0x8 => match n {
+                0x0 => Move(x, y),
+                0x1 => Or(x, y),
+                0x2 => And(x, y),
+                0x3 => Xor(x, y),
+                0x4 => AddReg(x, y),
+                0x5 => Sub(x, y),
+                0x6 => ShiftRight(x, y),
+                0x7 => SubReverse(x, y),
+                0xE => ShiftLeft(x, y),
+                _ => return None,
+            },
+            0x9 => match n {
+                0x0 => SkipNeReg(x, y),
+                _ => return None,
+            },
+            0xA => LoadI(nnn),
+            0xB => JumpOffset(nnn),
+            0xC => Random(x, nn),
+            0xD => Draw(x, y, n),
+            0xE => match nn {
+                0x9E => SkipKey(x),
+                0xA1 => SkipNotKey(x),
+                _ => return None,
+            },
+            _ => match nn {
+                0x00 if x == 0 => LoadILong,
+                0x01 => Plane(x),
+                0x02 if x == 0 => Audio,
+                0x07 => GetDelay(x),
+                0x0A => WaitKey(x),
+                0x15 => SetDelay(x),
+                0x18 => SetSound(x),
+                0x1E => AddI(x),
+                0x29 => Font(x),
+                0x30 => BigFont(x),
+                0x33 => Bcd(x),
+                0x3A => Pitch(x),
+                0x55 => Store(x),
+                0x65 => Load(x),
+                0x75 => StoreRpl(x),
+                0x85 => LoadRpl(x),
+                _ => return None,
+            },
+        };
+        Some(instruction)
+    }
+
+    /// The 16-bit opcode for this instruction, the inverse of `decode`
+    pub fn encode(&self) -> u16 {
+        use Instruction::*;
+
+        fn xy(prefix: u16, x: u8, y: u8, n: u16) -> u16 {
+            prefix << 12 | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | n
+        }
+        fn xnn(prefix: u16, x: u8, nn: u8) -> u16 {
+            prefix << 12 | (x as u16 & 0xF) << 8 | nn as u16
+        }
+        match *self {
+            Sys(nnn) => nnn & 0x0FFF,
+            Cls => 0x00E0,
+            Ret => 0x00EE,
+            ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            ScrollUp(n) => 0x00D0 | (n as u16 & 0xF),
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            Lores => 0x00FE,
+            Hires => 0x00FF,
+            Jump(nnn) => 0x1000 | (nnn & 0x0FFF),
+            Call(nnn) => 0x2000 | (nnn & 0x0FFF),
+            SkipEqImm(x, nn) => xnn(0x3, x, nn),
+            SkipNeImm(x, nn) => xnn(0x4, x, nn),
+            SkipEqReg(x, y) => xy(0x5, x, y, 0x0),
+            SaveRange(x, y) => xy(0x5, x, y, 0x2),
+            LoadRange(x, y) => xy(0x5, x, y, 0x3),
+            LoadImm(x, nn) => xnn(0x6, x, nn),
+            AddImm(x, nn) => xnn(0x7, x, nn),
+            Move(x, y) => xy(0x8, x, y, 0x0),
+            Or(x, y) => xy(0x8, x, y, 0x1),
+            And(x, y) => xy(0x8, x, y, 0x2),
+            Xor(x, y) => xy(0x8, x, y, 0x3),
+            AddReg(x, y) => xy(0x8, x, y, 0x4),
+            Sub(x, y) => xy(0x8, x, y, 0x5),
+            ShiftRight(x, y) => xy(0x8, x, y, 0x6),
+            SubReverse(x, y) => xy(0x8, x, y, 0x7),
+            ShiftLeft(x, y) => xy(0x8, x, y, 0xE),
+            SkipNeReg(x, y) => xy(0x9, x, y, 0x0),
+            LoadI(nnn) => 0xA000 | (nnn & 0x0FFF),
+            JumpOffset(nnn) => 0xB000 | (nnn & 0x0FFF),
+            Random(x, nn) => xnn(0xC, x, nn),
+            Draw(x, y, n) => xy(0xD, x, y, n as u16 & 0xF),
+            SkipKey(x) => xnn(0xE, x, 0x9E),
+            SkipNotKey(x) => xnn(0xE, x, 0xA1),
+            LoadILong => 0xF000,
+            Plane(n) => xnn(0xF, n, 0x01),
+            Audio => 0xF002,
+            GetDelay(x) => xnn(0xF, x, 0x07),
+            WaitKey(x) => xnn(0xF, x, 0x0A),
+            SetDelay(x) => xnn(0xF, x, 0x15),
+            SetSound(x) => xnn(0xF, x, 0x18),
+            AddI(x) => xnn(0xF, x, 0x1E),
+            Font(x) => xnn(0xF, x, 0x29),
+            BigFont(x) => xnn(0xF, x, 0x30),
+            Bcd(x) => xnn(0xF, x, 0x33),
+            Pitch(x) => xnn(0xF, x, 0x3A),
+            Store(x) => xnn(0xF, x, 0x55),
+            Load(x) => xnn(0xF, x, 0x65),
+            StoreRpl(x) => xnn(0xF, x, 0x75),
+            LoadRpl(x) => xnn(0xF, x, 0x85),
+        }
+    }
+
+    /// The opcode pattern and mnemonic, the same for every instruction of a kind, e.g. `8XY4 ADD`
+    pub fn class(&self) -> &'static str {
+        use Instruction::*;
+
+        match self {
+            Sys(_) => "0NNN SYS",
+            Cls => "00E0 CLS",
+            Ret => "00EE RET",
+            ScrollDown(_) => "00CN SCD",
+            ScrollUp(_) => "00DN SCU",
+            ScrollRight => "00FB SCR",
+            ScrollLeft => "00FC SCL",
+            Exit => "00FD EXIT",
+            Lores => "00FE LOW",
+            Hires => "00FF HIGH",
+            Jump(_) => "1NNN JP",
+            Call(_) => "2NNN CALL",
+            SkipEqImm(..) => "3XNN SE",
+            SkipNeImm(..) => "4XNN SNE",
+            SkipEqReg(..) => "5XY0 SE",
+            SaveRange(..) => "5XY2 SAVE",
+            LoadRange(..) => "5XY3 LOAD",
+            LoadImm(..) => "6XNN LD",
+            AddImm(..) => "7XNN ADD",
+            Move(..) => "8XY0 LD",
+            Or(..) => "8XY1 OR",
+            And(..) => "8XY2 AND",
+            Xor(..) => "8XY3 XOR",
+            AddReg(..) => "8XY4 ADD",
+            Sub(..) => "8XY5 SUB",
+            ShiftRight(..) => "8XY6 SHR",
+            SubReverse(..) => "8XY7 SUBN",
+            ShiftLeft(..) => "8XYE SHL",
+            SkipNeReg(..) => "9XY0 SNE",
+            LoadI(_) => "ANNN LD I",
+            JumpOffset(_) => "BNNN JP V0",
+            Random(..) => "CXNN RND",
+            Draw(..) => "DXYN DRW",
+            SkipKey(_) => "EX9E SKP",
+            SkipNotKey(_) => "EXA1 SKNP",
+            LoadILong => "F000 LD I, LONG",
+            Plane(_) => "FN01 PLANE",
+            Audio => "F002 AUDIO",
+            GetDelay(_) => "FX07 LD DT",
+            WaitKey(_) => "FX0A LD K",
+            SetDelay(_) => "FX15 LD DT",
+            SetSound(_) => "FX18 LD ST",
+            AddI(_) => "FX1E ADD I",
+            Font(_) => "FX29 LD F",
+            BigFont(_) => "FX30 LD HF",
+            Bcd(_) => "FX33 LD B",
+            Pitch(_) => "FX3A PITCH",
+            Store(_) => "FX55 LD [I]",
+            Load(_) => "FX65 LD [I]",
+            StoreRpl(_) => "FX75 LD R",
+            LoadRpl(_) => "FX85 LD R",
+        }
+    }
+
+    /// Only understood by XO-CHIP interpreters
+    pub fn is_xochip(&self) -> bool {
+        use Instruction::*;
+
+        matches!(
+            self,
+            ScrollUp(_) | SaveRange(..) | LoadRange(..) | LoadILong | Plane(_) | Audio | Pitch(_)
+        )
+    }
+}
+
+/// Cowgod style mnemonics, extended with the SUPER-CHIP and XO-CHIP instructions
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+
+        match *self {
+            Sys(nnn) => write!(f, "SYS {:#05X}", nnn),
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            ScrollDown(n) => write!(f, "SCD {}", n),
+            ScrollUp(n) => write!(f, "SCU {}", n),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Lores => write!(f, "LOW"),
+            Hires => write!(f, "HIGH"),
+            Jump(nnn) => write!(f, "JP {:#05X}", nnn),
+            Call(nnn) => write!(f, "CALL {:#05X}", nnn),
+            SkipEqImm(x, nn) => write!(f, "SE V{:X}, {:#04X}", x, nn),
+            SkipNeImm(x, nn) => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+            SkipEqReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            SaveRange(x, y) => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            LoadRange(x, y) => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            LoadImm(x, nn) => write!(f, "LD V{:X}, {:#04X}", x, nn),
+            AddImm(x, nn) => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+            Move(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            ShiftRight(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            SubReverse(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            ShiftLeft(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SkipNeReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LoadI(nnn) => write!(f, "LD I, {:#05X}", nnn),
+            JumpOffset(nnn) => write!(f, "JP V0, {:#05X}", nnn),
+            Random(x, nn) => write!(f, "RND V{:X}, {:#04X}", x, nn),
+            Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            SkipKey(x) => write!(f, "SKP V{:X}", x),
+            SkipNotKey(x) => write!(f, "SKNP V{:X}", x),
+            LoadILong => write!(f, "LD I, LONG"),
+            Plane(n) => write!(f, "PLANE {}", n),
+            Audio => write!(f, "AUDIO"),
+            GetDelay(x) => write!(f, "LD V{:X}, DT", x),
+            WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Font(x) => write!(f, "LD F, V{:X}", x),
+            BigFont(x) => write!(f, "LD HF, V{:X}", x),
+            Bcd(x) => write!(f, "LD B, V{:X}", x),
+            Pitch(x) => write!(f, "PITCH V{:X}", x),
+            Store(x) => write!(f, "LD [I], V{:X}", x),
+            Load(x) => write!(f, "LD V{:X}, [I]", x),
+            StoreRpl(x) => write!(f, "LD R, V{:X}", x),
+            LoadRpl(x) => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
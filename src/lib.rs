@@ -0,0 +1,41 @@
+//! CHIP-8, SUPER-CHIP and XO-CHIP emulator core.
+//!
+//! The core has no terminal dependencies. The `terminal` feature (on by
+//! default) adds the tui frontend glue used by the `chipterm` binary. The
+//! `dap` and `scripting` features, also on by default, add the Debug Adapter
+//! Protocol server and the Rhai scripting hooks.
+
+pub mod access;
+pub mod asm;
+pub mod audio;
+pub mod core;
+pub mod coverage;
+#[cfg(feature = "dap")]
+pub mod dap;
+pub mod debugger;
+pub mod describe;
+pub mod disasm;
+pub mod display;
+pub mod gdb;
+pub mod instruction;
+pub mod movie;
+pub mod platform;
+pub mod profile;
+pub mod quirks;
+pub mod rewind;
+pub mod savestate;
+pub mod scheduler;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod symbols;
+#[cfg(feature = "terminal")]
+pub mod terminal;
+pub mod trace;
+
+pub use crate::core::{Chip8, ExecError};
+pub use crate::display::Display;
+pub use crate::instruction::Instruction;
+pub use crate::platform::Platform;
+pub use crate::quirks::{Preset, Quirks};
+pub use crate::rewind::Rewind;
+pub use crate::scheduler::Scheduler;
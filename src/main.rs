@@ -1,21 +1,41 @@
-mod core;
-mod display;
+mod commands;
+mod memview;
 mod utils;
 
-use crate::core::Chip8;
-use crate::utils::BUTTONMAP;
-use display::Display;
-use structopt::StructOpt;
+use crate::memview::{Follow, MemoryView};
+use crate::utils::{state_dir, BUTTONMAP};
+use chipterm::audio::{AudioSink, BellSink, Buzzer, NullSink, SinkKind, VisualSink, WavSink};
+use chipterm::coverage::{Coverage, Summary};
+use chipterm::debugger::{parse_address, Debugger, Stop, Watchpoint};
+use chipterm::describe::Before;
+use chipterm::disasm::ORIGIN;
+use chipterm::gdb::{self, Packet, Reply};
+use chipterm::movie::{Input, Movie, Playback};
+use chipterm::profile::Profiler;
+use chipterm::savestate::{self, SaveStateError};
+use chipterm::scheduler::FRAME_RATE;
+use chipterm::script::{Hook, Script};
+use chipterm::symbols::Symbols;
+use chipterm::trace::Tracer;
+use chipterm::{Chip8, ExecError, Instruction, Platform, Preset, Rewind, Scheduler};
+use structopt::{
+    clap::{self, AppSettings},
+    StructOpt,
+};
 
-use rand::Rng;
-use signal_hook;
 use signal_hook::consts::signal::SIGWINCH;
 use std::{
-    io::{self, Read},
-    path::PathBuf,
-    sync::mpsc::channel,
+    cell::Cell,
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    net::{TcpListener, TcpStream},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::mpsc::{channel, RecvTimeoutError},
     thread,
-    time::{Duration, Instant},
+    time::Instant,
 };
 use termion::{
     event::Event,
@@ -25,64 +45,604 @@ use termion::{
 };
 use tui::backend::TermionBackend;
 use tui::layout::{Alignment, Constraint, Direction, Layout, Rect};
-use tui::style::Color;
+use tui::style::{Color, Modifier};
 use tui::text::{Span, Spans};
-use tui::widgets::{canvas::Canvas, Block, Borders, Paragraph, Wrap};
+use tui::widgets::{canvas::Canvas, Block, Borders, Clear, Paragraph, Wrap};
 use tui::Terminal;
 use tui::{style::Style, symbols};
 
+/// Instructions kept in the description history
+const HISTORY_LEN: usize = 100;
+
+/// What a line typed into the prompt is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptKind {
+    Breakpoint,
+    Watchpoint,
+    GoTo,
+}
+
+impl PromptKind {
+    fn title(self) -> &'static str {
+        match self {
+            PromptKind::Breakpoint => "Toggle breakpoint at address",
+            PromptKind::Watchpoint => "Toggle watchpoint: [r|w|rw] V0-VF|I|addr[-addr][=value]",
+            PromptKind::GoTo => "Show memory at address",
+        }
+    }
+}
+
+/// A line of text being typed in the debug view
 #[derive(Debug, Clone)]
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub input: String,
+}
+
+/// Everything the main loop reacts to besides the frame clock
+#[derive(Debug)]
+pub enum AppEvent {
+    Input(Event),
+    Resize,
+    /// A debugger connected to `--gdb`, replies go to this stream
+    GdbConnected(TcpStream),
+    Gdb(gdb::Packet),
+    GdbDisconnected,
+}
+
+#[derive(Debug)]
 pub struct App {
     pub debug: bool,
     pub show_real_controls: bool,
     pub rewind: u8,
     pub paused: bool,
+    /// Last execution error, the emulator stays paused while it is shown
+    pub error: Option<ExecError>,
+    /// Raised by the visual audio sink while the buzzer sounds
+    pub flash: Rc<Cell<bool>>,
+    /// CXNN seed, kept across resets so a run can be reproduced
+    pub seed: u64,
+    /// Identifies the ROM in save states
+    pub rom_hash: u64,
+    /// Size of the ROM, highlighted in the memory panel
+    pub rom_len: usize,
+    /// Message for the title bar and the frame it disappears at
+    pub status: Option<(String, u64)>,
+    /// Input movie being recorded with `--record`
+    pub recording: Option<Movie>,
+    /// Input movie being replayed with `--play`, keyboard input is ignored meanwhile
+    pub playback: Option<Playback>,
+    pub debugger: Debugger,
+    /// Why the debugger last paused the emulator
+    pub stop: Option<Stop>,
+    /// Set on resume so the instruction the debugger stopped at can run
+    pub resuming: bool,
+    pub prompt: Option<Prompt>,
+    pub memory: MemoryView,
+    /// Labels from `--symbols`
+    pub symbols: Symbols,
+    /// Descriptions of the last instructions run in the debug view, newest last
+    pub history: VecDeque<String>,
+    /// Connection to the debugger attached through `--gdb`
+    pub gdb: Option<TcpStream>,
+    /// The debugger continued and waits to hear why the emulator stopped
+    pub gdb_running: bool,
+    /// Instruction trace from `--trace`
+    pub tracer: Option<Tracer<BufWriter<File>>>,
+    /// Script from `--script`, dropped after its first error
+    pub script: Option<Script>,
+    /// Lines the script printed, shown in the title bar at the end of the frame
+    pub script_output: Vec<String>,
+    /// Where on_instruction paused, the instruction runs without the hook on resume
+    pub script_held: Option<u16>,
+    /// Execution counts from `--profile`
+    pub profiler: Option<Profiler>,
+    /// The debug view shows the profile instead of memory
+    pub show_profile: bool,
+    /// Bytes executed, read and written, from `--coverage`
+    pub coverage: Option<Coverage>,
     pub args: AppArgs,
 }
 
 impl App {
     fn new(args: AppArgs) -> Self {
+        let mut debugger = Debugger::new();
+        for &addr in &args.breakpoints {
+            debugger.add_breakpoint(addr);
+        }
+        for &watchpoint in &args.watchpoints {
+            debugger.add_watchpoint(watchpoint);
+        }
         App {
-            args,
             debug: false,
             show_real_controls: true,
             rewind: 0,
             paused: false,
+            error: None,
+            flash: Rc::new(Cell::new(false)),
+            seed: args.seed.unwrap_or_else(rand::random),
+            rom_hash: 0,
+            rom_len: 0,
+            status: None,
+            recording: None,
+            playback: None,
+            debugger,
+            stop: None,
+            resuming: false,
+            prompt: None,
+            memory: MemoryView::default(),
+            symbols: Symbols::new(),
+            history: VecDeque::with_capacity(HISTORY_LEN),
+            gdb: None,
+            gdb_running: false,
+            tracer: None,
+            script: None,
+            script_output: Vec::new(),
+            script_held: None,
+            profiler: None,
+            show_profile: false,
+            coverage: None,
+            args,
+        }
+    }
+
+    fn audio_sink(&self) -> Box<dyn AudioSink> {
+        if let Some(path) = &self.args.wav {
+            return Box::new(WavSink::new(path.clone()));
+        }
+        match self.args.audio {
+            SinkKind::Bell => Box::new(BellSink),
+            SinkKind::Visual => Box::new(VisualSink::new(self.flash.clone())),
+            SinkKind::None => Box::new(NullSink),
+        }
+    }
+
+    /// Executes one instruction, pausing on error instead of bringing the terminal down
+    fn step(&mut self, chip8: &mut Chip8) {
+        if chip8.exited {
+            return;
+        }
+        let pc = chip8.program_counter;
+        if self.script.is_some() && self.script_held.take() != Some(pc) {
+            let opcode = match (chip8.mem.get(pc as usize), chip8.mem.get(pc as usize + 1)) {
+                (Some(&hi), Some(&lo)) => (hi as u16) << 8 | lo as u16,
+                _ => 0,
+            };
+            let paused = self.paused;
+            self.call_script(chip8, Hook::Instruction { pc, opcode });
+            // Paused by the script, the instruction runs on resume
+            if self.paused && !paused {
+                self.script_held = Some(pc);
+                return;
+            }
+        }
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(chip8);
         }
+        chip8.log_accesses(self.debug || self.coverage.is_some());
+        // Descriptions are only worth their formatting while the debug view shows them
+        let before = Some(Before::of(chip8)).filter(|_| self.debug);
+        let result = if self.movie_active() {
+            chip8.emulation_cycle().map(|()| None)
+        } else {
+            self.debugger.step(chip8)
+        };
+        if result.is_ok() {
+            if let Some(profiler) = &mut self.profiler {
+                profiler.record(pc, chip8);
+            }
+            if let Some(coverage) = &mut self.coverage {
+                coverage.record(pc, chip8.opcode, chip8.accesses());
+            }
+            if let Some(before) = &before {
+                if self.history.len() == HISTORY_LEN {
+                    self.history.pop_front();
+                }
+                self.history
+                    .push_back(format!("{:03X}: {}", pc, chip8.describe(before)));
+            }
+        }
+        match result {
+            Ok(None) => {}
+            Ok(Some(stop)) => {
+                self.paused = true;
+                if let Some(tracer) = &mut self.tracer {
+                    tracer.arm();
+                }
+                self.call_script(chip8, Hook::Break(&stop.to_string()));
+                self.stop = Some(stop);
+            }
+            Err(err) => {
+                self.paused = true;
+                self.call_script(chip8, Hook::Break(&err.to_string()));
+                self.error = Some(err);
+            }
+        }
+        if self.debug {
+            self.memory.after_step(chip8);
+        }
+    }
+
+    /// Runs a script hook, applying the pause or resume it asked for
+    fn call_script(&mut self, chip8: &mut Chip8, hook: Hook) {
+        let script = match &mut self.script {
+            Some(script) => script,
+            None => return,
+        };
+        let result = script.call(hook, chip8, &mut self.paused);
+        self.script_output.extend(script.take_output());
+        if let Err(err) = result {
+            self.paused = true;
+            self.script = None;
+            self.script_output
+                .push(format!("script error, script stopped: {}", err));
+        }
+    }
+
+    /// Instructions the rewind buffer holds
+    fn rewind_capacity(&self) -> usize {
+        // The arguments are checked when parsed, a movie's cycles per frame is not
+        rewind_capacity(self.args.rewind_seconds, self.args.cycles_per_frame).unwrap_or(usize::MAX)
+    }
+
+    /// A freshly reset machine for the selected platform and quirks
+    fn new_chip8(&self) -> Chip8 {
+        if let Some(playback) = &self.playback {
+            return playback.chip8();
+        }
+        let preset = self
+            .args
+            .quirks
+            .unwrap_or_else(|| self.args.platform.default_preset());
+        Chip8::new(self.args.platform, preset.quirks(), self.seed)
+    }
+
+    fn rompath(&self) -> &Path {
+        self.args
+            .rompath
+            .as_deref()
+            .expect("a ROM is required without a subcommand")
+    }
+
+    fn slot_path(&self, slot: u8) -> PathBuf {
+        state_dir(self.rompath(), self.rom_hash).join(format!("slot{}.state", slot))
+    }
+
+    /// Writes the machine to a numbered save slot
+    fn save_state(&self, chip8: &Chip8, slot: u8) -> io::Result<()> {
+        let path = self.slot_path(slot);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut out = BufWriter::new(File::create(path)?);
+        savestate::save(chip8, self.rom_hash, &mut out)?;
+        out.flush()
+    }
+
+    fn load_state(&self, slot: u8) -> Result<Chip8, SaveStateError> {
+        let mut input = BufReader::new(File::open(self.slot_path(slot))?);
+        savestate::load(&mut input, self.rom_hash)
+    }
+
+    /// Presses a keypad key, unless a movie is providing the input
+    fn press_key(&mut self, chip8: &mut Chip8, key: u8, frame: u64) {
+        if self.playback.is_some() {
+            return;
+        }
+        chip8.press_key(key);
+        if let Some(movie) = &mut self.recording {
+            movie.record(frame, Input::Press(key));
+        }
+    }
+
+    fn movie_active(&self) -> bool {
+        self.recording.is_some() || self.playback.is_some()
+    }
+
+    /// Pauses before the next instruction if the debugger wants to stop there
+    fn check_debugger(&mut self, chip8: &mut Chip8) -> bool {
+        // Stopping would make a movie diverge from its recording
+        if std::mem::take(&mut self.resuming) || self.movie_active() {
+            return false;
+        }
+        self.stop = self.debugger.check(chip8);
+        if let Some(stop) = &self.stop {
+            self.paused = true;
+            if let Some(tracer) = &mut self.tracer {
+                tracer.arm();
+            }
+            let reason = stop.to_string();
+            self.call_script(chip8, Hook::Break(&reason));
+        }
+        self.paused
+    }
+
+    /// Acts on the line typed into the prompt
+    fn submit_prompt(&mut self, prompt: Prompt, frame: u64) {
+        let status = match prompt.kind {
+            PromptKind::Breakpoint => match parse_address(&prompt.input) {
+                Ok(addr) if self.debugger.toggle_breakpoint(addr) => {
+                    format!("breakpoint set at {:#05X}", addr)
+                }
+                Ok(addr) => format!("breakpoint cleared at {:#05X}", addr),
+                Err(err) => err,
+            },
+            PromptKind::Watchpoint => match prompt.input.parse::<Watchpoint>() {
+                Ok(watchpoint) if self.debugger.toggle_watchpoint(watchpoint) => {
+                    format!("watchpoint '{}' set", watchpoint)
+                }
+                Ok(watchpoint) => format!("watchpoint '{}' cleared", watchpoint),
+                Err(err) => err,
+            },
+            PromptKind::GoTo => match parse_address(&prompt.input) {
+                Ok(addr) => {
+                    self.memory.go_to(addr);
+                    return;
+                }
+                Err(err) => err,
+            },
+        };
+        self.show_status(status, frame);
+    }
+
+    /// Sends a packet to the attached debugger, dropping the connection if that fails
+    fn gdb_send(&mut self, data: &str) {
+        if let Some(stream) = &mut self.gdb {
+            if gdb::write_packet(stream, data).is_err() {
+                self.gdb = None;
+                self.gdb_running = false;
+            }
+        }
+    }
+
+    /// Tells a debugger waiting on a continue that the emulator stopped
+    fn gdb_check_stopped(&mut self, chip8: &Chip8) {
+        if self.gdb_running && (self.paused || chip8.exited) {
+            self.gdb_running = false;
+            let reply = gdb::stop_reply(self.stop.as_ref(), self.error.as_ref(), chip8.exited);
+            self.gdb_send(&reply);
+        }
+    }
+
+    /// Shows `status` in the title bar for a few seconds
+    fn show_status(&mut self, status: String, frame: u64) {
+        self.status = Some((status, frame + 3 * FRAME_RATE as u64));
     }
 }
 
 #[derive(Debug, Clone, StructOpt)]
-#[structopt(name = "chipterm", about = "Chip8 emulator in terminal.")]
+#[structopt(
+    name = "chipterm",
+    about = "Chip8 emulator in terminal.",
+    usage = "chipterm [OPTIONS] <rompath>\n    chipterm <SUBCOMMAND>",
+    setting = AppSettings::ArgsNegateSubcommands
+)]
 pub struct AppArgs {
+    #[structopt(subcommand)]
+    command: Option<Command>,
     //path to chip8 rom
     #[structopt(parse(from_os_str))]
-    rompath: PathBuf,
+    rompath: Option<PathBuf>,
+    /// Platform: chip8, schip or xochip
+    #[structopt(long, default_value = "chip8")]
+    platform: Platform,
+    /// Quirk preset: vip, chip48, schip, xochip or modern [default: depends on platform]
+    #[structopt(long)]
+    quirks: Option<Preset>,
+    /// Buzzer output: bell, visual or none
+    #[structopt(long, default_value = "bell")]
+    audio: SinkKind,
+    /// Render the buzzer to a WAV file instead
+    #[structopt(long, parse(from_os_str))]
+    wav: Option<PathBuf>,
+    /// Seed for the CXNN random number generator [default: random]
+    #[structopt(long)]
+    seed: Option<u64>,
+    /// Instructions executed per 60Hz frame
+    #[structopt(long, default_value = "12")]
+    cycles_per_frame: u32,
+    /// How far back rewinding can go, in seconds of emulation
+    #[structopt(long, default_value = "30")]
+    rewind_seconds: u32,
+    /// Record key presses to an input movie
+    #[structopt(long, parse(from_os_str), conflicts_with = "play")]
+    record: Option<PathBuf>,
+    /// Replay an input movie, then print the final state hash
+    #[structopt(long, parse(from_os_str))]
+    play: Option<PathBuf>,
+    /// Pause when the program counter reaches this address, can be repeated
+    #[structopt(long = "break", number_of_values = 1, parse(try_from_str = parse_address))]
+    breakpoints: Vec<u16>,
+    /// Pause when a register or memory is accessed, e.g. "w V3", "rw 0x300-0x305=7", can be repeated
+    #[structopt(long = "watch", number_of_values = 1)]
+    watchpoints: Vec<Watchpoint>,
+    /// Write a line per executed instruction with the registers before it runs
+    #[structopt(long, parse(from_os_str))]
+    trace: Option<PathBuf>,
+    /// Only trace instructions in this address range, e.g. 0x200-0x2FF
+    #[structopt(long, requires = "trace", parse(try_from_str = parse_range))]
+    trace_range: Option<RangeInclusive<u16>>,
+    /// Only start tracing once a breakpoint or watchpoint is hit
+    #[structopt(long, requires = "trace")]
+    trace_after_break: bool,
+    /// Serve the GDB remote protocol on this localhost port, starting paused
+    #[structopt(long, conflicts_with_all = &["record", "play"])]
+    gdb: Option<u16>,
+    /// Symbol file naming the subroutines on the stack, as written by `asm --symbols`
+    #[structopt(long, parse(from_os_str))]
+    symbols: Option<PathBuf>,
+    /// Count executions per address, opcode class and subroutine, and report them on exit
+    #[structopt(long)]
+    profile: bool,
+    /// Write the profile report to a file instead of the terminal
+    #[structopt(long, requires = "profile", parse(from_os_str))]
+    profile_report: Option<PathBuf>,
+    /// Track which bytes run, are read and are written, merging into this file on exit
+    #[structopt(long, parse(from_os_str))]
+    coverage: Option<PathBuf>,
+    /// Rhai script with on_frame, on_instruction and on_break hooks
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["record", "play"])]
+    script: Option<PathBuf>,
 }
 
-fn main() -> Result<(), io::Error> {
-    let mut app = App::new(AppArgs::from_args());
-    let mut chip8 = Chip8::new();
+// Tools that run instead of the emulator
+#[derive(Debug, Clone, StructOpt)]
+pub enum Command {
+    /// Disassemble a ROM, following jumps, calls and skips from 0x200
+    Disasm {
+        #[structopt(parse(from_os_str))]
+        rompath: PathBuf,
+        /// Platform: chip8, schip or xochip
+        #[structopt(long, default_value = "chip8")]
+        platform: Platform,
+        /// Write the listing to a file instead of stdout
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Leave out the address and raw byte comments
+        #[structopt(long)]
+        no_addresses: bool,
+    },
+    /// Assemble a source file in the disassembly syntax into a ROM
+    Asm {
+        #[structopt(parse(from_os_str))]
+        source: PathBuf,
+        /// Where to write the ROM, defaults to the source path with a .ch8 extension
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Also write the label addresses to a symbol file for --symbols
+        #[structopt(long, parse(from_os_str))]
+        symbols: Option<PathBuf>,
+        /// Also write the source line of every instruction, for `dap` breakpoints by line
+        #[structopt(long, parse(from_os_str))]
+        source_map: Option<PathBuf>,
+    },
+    /// Serve the Debug Adapter Protocol for editors, on stdin and stdout by default
+    Dap {
+        /// Listen on this localhost port instead, serving one session at a time
+        #[structopt(long)]
+        port: Option<u16>,
+    },
+}
 
-    let romdata = std::fs::read(&app.args.rompath)?;
+fn main() -> Result<(), io::Error> {
+    let args = AppArgs::from_args();
+    if let Some(command) = args.command {
+        return commands::run(command);
+    }
+    if args.rompath.is_none() {
+        clap::Error::with_description(
+            "The following required arguments were not provided:\n    <rompath>",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit();
+    }
+    if rewind_capacity(args.rewind_seconds, args.cycles_per_frame).is_none() {
+        clap::Error::with_description(
+            "--rewind-seconds and --cycles-per-frame make the rewind buffer too large",
+            clap::ErrorKind::ValueValidation,
+        )
+        .exit();
+    }
+    let mut app = App::new(args);
+    let romdata = std::fs::read(app.rompath())?;
+    app.rom_hash = savestate::rom_hash(&romdata);
+    app.rom_len = romdata.len();
+    if let Some(path) = &app.args.symbols {
+        app.symbols = Symbols::read(BufReader::new(File::open(path)?)).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), err),
+            )
+        })?;
+    }
+    if app.args.profile {
+        app.profiler = Some(Profiler::new());
+        app.show_profile = true;
+    }
+    if let Some(path) = &app.args.script {
+        app.script = Some(Script::load(path).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), err),
+            )
+        })?);
+    }
+    if let Some(path) = &app.args.play {
+        let movie = Movie::read(BufReader::new(File::open(path)?)).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), err),
+            )
+        })?;
+        if movie.rom_hash != app.rom_hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: movie was recorded with another ROM", path.display()),
+            ));
+        }
+        app.args.cycles_per_frame = movie.cycles_per_frame;
+        app.playback = Some(Playback::new(movie));
+    }
+    let mut chip8 = app.new_chip8();
     chip8.load_game(&romdata)?;
+    if let Some(path) = &app.args.coverage {
+        let mut coverage = Coverage::new(app.rom_hash, chip8.mem.len());
+        // Runs add up in the same file
+        if path.exists() {
+            let old = Coverage::read(BufReader::new(File::open(path)?)).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), err),
+                )
+            })?;
+            if old.rom_hash != app.rom_hash {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: coverage was recorded with another ROM", path.display()),
+                ));
+            }
+            coverage.merge(&old);
+        }
+        app.coverage = Some(coverage);
+    }
+    if app.args.record.is_some() {
+        app.recording = Some(Movie::new(&chip8, app.rom_hash, app.args.cycles_per_frame));
+    }
+    if let Some(path) = &app.args.trace {
+        let tracer = Tracer::new(
+            BufWriter::new(File::create(path)?),
+            app.args.trace_range.clone(),
+        );
+        app.tracer = Some(if app.args.trace_after_break {
+            tracer.disarmed()
+        } else {
+            tracer
+        });
+    }
+    let gdb_listener = match app.args.gdb {
+        Some(port) => {
+            app.paused = true;
+            app.show_status(format!("waiting for gdb on 127.0.0.1:{}", port), 0);
+            Some(TcpListener::bind(("127.0.0.1", port))?)
+        }
+        None => None,
+    };
+    let mut buzzer = Buzzer::new(app.audio_sink());
 
     let stdin = io::stdin();
     let stdout = io::stdout().into_raw_mode()?;
 
     let (tx, rx) = channel();
     let input_tx = tx.clone();
-    let signal_tx = tx.clone();
-    let cpu_tick_tx = tx.clone();
-    let draw_tick_tx = tx.clone();
-    let delay_timer_tick_tx = tx.clone();
-    let input_clear_tx = tx.clone();
+    let gdb_tx = tx.clone();
+    let signal_tx = tx;
 
     let mut terminal = Terminal::new(TermionBackend::new(stdout))?;
     let mut duration = std::time::Instant::now();
+    let mut scheduler = Scheduler::new(app.args.cycles_per_frame);
 
     terminal.clear()?;
-    let mut emulation_state = vec![chip8.clone()];
+    let mut emulation_state = Rewind::new(app.rewind_capacity());
     draw_frame(
         &mut terminal,
         &mut duration,
@@ -94,93 +654,73 @@ fn main() -> Result<(), io::Error> {
     // Input listener thread
     thread::spawn(move || {
         for event in stdin.events() {
-            input_tx.send(event.unwrap()).unwrap();
+            input_tx.send(AppEvent::Input(event.unwrap())).unwrap();
         }
     });
 
     // Resize listener thread
     thread::spawn(move || {
-        let mut signals = signal_hook::iterator::Signals::new(&[SIGWINCH]).unwrap();
+        let mut signals = signal_hook::iterator::Signals::new([SIGWINCH]).unwrap();
         for _ in signals.forever() {
-            signal_tx.send(Event::Key(Key::Ctrl('l'))).unwrap();
+            signal_tx.send(AppEvent::Resize).unwrap();
         }
     });
 
-    // Cpu tick event (700 instructions per second)
-    thread::spawn(move || loop {
-        thread::sleep(Duration::from_nanos(700000));
-        cpu_tick_tx.send(Event::Key(Key::Null)).unwrap();
-    });
-
-    thread::spawn(move || loop {
-        thread::sleep(Duration::from_nanos(16666667));
-        draw_tick_tx.send(Event::Key(Key::F(15))).unwrap();
-    });
-
-    // Timer tick event (60Hz)
-    thread::spawn(move || loop {
-        thread::sleep(Duration::from_nanos(16666667));
-        delay_timer_tick_tx.send(Event::Key(Key::F(13))).unwrap();
-    });
-
-    thread::spawn(move || loop {
-        thread::sleep(Duration::from_nanos(16666667 * 3));
-        input_clear_tx.send(Event::Key(Key::F(14))).unwrap();
-    });
-
-    // Main loop for events processing
-    for event in rx.iter() {
-        match event {
-            // ctrl keys
-            Event::Key(Key::Ctrl('c')) => break,
-            Event::Key(Key::Ctrl('d')) => app.debug = !app.debug,
-            Event::Key(Key::Ctrl('o')) => app.show_real_controls = !app.show_real_controls,
-            Event::Key(Key::Ctrl('r')) => {
-                chip8 = Chip8::new();
-                chip8.load_game(&romdata)?;
+    // Debugger listener thread, serves one connection at a time
+    if let Some(listener) = gdb_listener {
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (writer, mut ack) = match (stream.try_clone(), stream.try_clone()) {
+                    (Ok(writer), Ok(ack)) => (writer, ack),
+                    _ => continue,
+                };
+                if gdb_tx.send(AppEvent::GdbConnected(writer)).is_err() {
+                    return;
+                }
+                let mut reader = BufReader::new(stream);
+                while let Ok(Some(packet)) = gdb::read_packet(&mut reader, &mut ack) {
+                    if gdb_tx.send(AppEvent::Gdb(packet)).is_err() {
+                        return;
+                    }
+                }
+                if gdb_tx.send(AppEvent::GdbDisconnected).is_err() {
+                    return;
+                }
             }
+        });
+    }
 
-            // contols
-            Event::Key(Key::Char('1')) => chip8.press_key(0x1),
-            Event::Key(Key::Char('2')) => chip8.press_key(0x2),
-            Event::Key(Key::Char('3')) => chip8.press_key(0x3),
-            Event::Key(Key::Char('4')) => chip8.press_key(0xC),
-            Event::Key(Key::Char('q')) => chip8.press_key(0x4),
-            Event::Key(Key::Char('w')) => chip8.press_key(0x5),
-            Event::Key(Key::Char('e')) => chip8.press_key(0x6),
-            Event::Key(Key::Char('r')) => chip8.press_key(0xD),
-            Event::Key(Key::Char('a')) => chip8.press_key(0x7),
-            Event::Key(Key::Char('s')) => chip8.press_key(0x8),
-            Event::Key(Key::Char('d')) => chip8.press_key(0x9),
-            Event::Key(Key::Char('f')) => chip8.press_key(0xE),
-            Event::Key(Key::Char('z')) => chip8.press_key(0xA),
-            Event::Key(Key::Char('x')) => chip8.press_key(0x0),
-            Event::Key(Key::Char('c')) => chip8.press_key(0xB),
-            Event::Key(Key::Char('v')) => chip8.press_key(0xF),
-
-            Event::Key(Key::F(13)) => {
-                chip8.decrement_delay_timer();
-            }
-
-            Event::Key(Key::Char('g')) => {
-                app.rewind = 2;
+    // Main loop, handles input as it comes and runs a frame every 1/60s
+    loop {
+        if let Some(playback) = &app.playback {
+            if playback.finished(scheduler.frame) {
+                break;
             }
-
-            Event::Key(Key::Char('<')) => {
-                if emulation_state.len() > 0 {
-                    chip8 = emulation_state.pop().unwrap()
-                }
-                draw_frame(
-                    &mut terminal,
-                    &mut duration,
-                    &mut app,
-                    &chip8,
-                    &emulation_state,
-                )?;
+        }
+        if scheduler.frame_due() {
+            run_frame(&mut app, &mut chip8, &mut emulation_state, &scheduler);
+            if let Some(movie) = &mut app.recording {
+                movie.end_frame(scheduler.frame, &chip8);
             }
-            Event::Key(Key::Char('>')) => {
-                emulation_state.push(chip8.clone());
-                chip8.emulation_cycle();
+            app.gdb_check_stopped(&chip8);
+            buzzer.update(chip8.buzzer_active());
+            draw_frame(
+                &mut terminal,
+                &mut duration,
+                &mut app,
+                &chip8,
+                &emulation_state,
+            )?;
+        }
+        let event = match rx.recv_timeout(scheduler.timeout()) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let key = match event {
+            AppEvent::Input(Event::Key(key)) => key,
+            AppEvent::Input(_) => continue,
+            AppEvent::Resize => {
                 draw_frame(
                     &mut terminal,
                     &mut duration,
@@ -188,82 +728,442 @@ fn main() -> Result<(), io::Error> {
                     &chip8,
                     &emulation_state,
                 )?;
+                continue;
             }
-
-            Event::Key(Key::Char('p')) => app.paused = !app.paused,
-
-            Event::Key(Key::F(14)) => {
-                for key in chip8.keys.iter_mut() {
-                    if key > &mut 0 {
-                        *key -= 1;
-                    }
-                }
-                if app.rewind > 0 {
-                    app.rewind -= 1;
+            AppEvent::GdbConnected(stream) => {
+                app.gdb = Some(stream);
+                app.gdb_running = false;
+                app.paused = true;
+                app.show_status(String::from("gdb attached"), scheduler.frame);
+                continue;
+            }
+            AppEvent::GdbDisconnected => {
+                app.gdb = None;
+                app.gdb_running = false;
+                app.show_status(String::from("gdb detached"), scheduler.frame);
+                continue;
+            }
+            AppEvent::Gdb(Packet::Interrupt) => {
+                app.paused = true;
+                if std::mem::take(&mut app.gdb_running) {
+                    app.gdb_send("S02");
                 }
+                continue;
             }
-
-            Event::Key(Key::F(15)) => {
-                // Draw canvas
+            AppEvent::Gdb(Packet::Command(command)) => {
+                match gdb::handle(&command, &mut chip8, &mut app.debugger) {
+                    Reply::Packet(reply) => app.gdb_send(&reply),
+                    Reply::Step => {
+                        app.stop = None;
+                        app.error = None;
+                        app.memory.clear_written();
+                        emulation_state.push(&mut chip8);
+                        app.step(&mut chip8);
+                        let reply =
+                            gdb::stop_reply(app.stop.as_ref(), app.error.as_ref(), chip8.exited);
+                        app.gdb_send(&reply);
+                    }
+                    Reply::Continue => {
+                        app.stop = None;
+                        app.error = None;
+                        app.paused = false;
+                        app.resuming = true;
+                        app.gdb_running = true;
+                    }
+                    Reply::Detach => {
+                        app.gdb_send("OK");
+                        app.gdb = None;
+                        app.paused = false;
+                        app.resuming = true;
+                    }
+                    Reply::Kill => break,
+                }
                 draw_frame(
                     &mut terminal,
                     &mut duration,
                     &mut app,
                     &chip8,
                     &emulation_state,
-                )?
-            }
-
-            // CPU timer tick
-            Event::Key(Key::Null) => {
-                // TODO decrement keyups (key is valid for two ticks)
-                if app.rewind > 0 && emulation_state.len() > 0 {
-                    chip8 = emulation_state.pop().unwrap();
-                } else if !app.paused {
-                    emulation_state.push(chip8.clone());
-                    // Read from program counter and execute opcode
-                    chip8.emulation_cycle();
+                )?;
+                continue;
+            }
+        };
+        if let Some(mut prompt) = app.prompt.take() {
+            match key {
+                Key::Ctrl('c') => break,
+                Key::Char('\n') => app.submit_prompt(prompt, scheduler.frame),
+                Key::Char(c) => {
+                    prompt.input.push(c);
+                    app.prompt = Some(prompt);
+                }
+                Key::Backspace => {
+                    prompt.input.pop();
+                    app.prompt = Some(prompt);
                 }
+                Key::Esc => {}
+                _ => app.prompt = Some(prompt),
             }
-            // Draw canvas
-            _ => draw_frame(
+            draw_frame(
                 &mut terminal,
                 &mut duration,
                 &mut app,
                 &chip8,
                 &emulation_state,
-            )?,
+            )?;
+            continue;
+        }
+        if app.movie_active() && breaks_movie(key) {
+            app.show_status(
+                String::from("not available while a movie is recording or playing"),
+                scheduler.frame,
+            );
+            continue;
+        }
+        match key {
+            // ctrl keys
+            Key::Ctrl('c') => break,
+            Key::Ctrl('d') => {
+                app.debug = !app.debug;
+                // History isn't kept outside the debug view, older lines would hide the gap
+                app.history.clear();
+            }
+            Key::Ctrl('o') => app.show_real_controls = !app.show_real_controls,
+            Key::Ctrl('r') => {
+                chip8 = app.new_chip8();
+                chip8.load_game(&romdata)?;
+                app.error = None;
+                app.history.clear();
+            }
+
+            // contols
+            Key::Char('1') => app.press_key(&mut chip8, 0x1, scheduler.frame),
+            Key::Char('2') => app.press_key(&mut chip8, 0x2, scheduler.frame),
+            Key::Char('3') => app.press_key(&mut chip8, 0x3, scheduler.frame),
+            Key::Char('4') => app.press_key(&mut chip8, 0xC, scheduler.frame),
+            Key::Char('q') => app.press_key(&mut chip8, 0x4, scheduler.frame),
+            Key::Char('w') => app.press_key(&mut chip8, 0x5, scheduler.frame),
+            Key::Char('e') => app.press_key(&mut chip8, 0x6, scheduler.frame),
+            Key::Char('r') => app.press_key(&mut chip8, 0xD, scheduler.frame),
+            Key::Char('a') => app.press_key(&mut chip8, 0x7, scheduler.frame),
+            Key::Char('s') => app.press_key(&mut chip8, 0x8, scheduler.frame),
+            Key::Char('d') => app.press_key(&mut chip8, 0x9, scheduler.frame),
+            Key::Char('f') => app.press_key(&mut chip8, 0xE, scheduler.frame),
+            Key::Char('z') => app.press_key(&mut chip8, 0xA, scheduler.frame),
+            Key::Char('x') => app.press_key(&mut chip8, 0x0, scheduler.frame),
+            Key::Char('c') => app.press_key(&mut chip8, 0xB, scheduler.frame),
+            Key::Char('v') => app.press_key(&mut chip8, 0xF, scheduler.frame),
+
+            Key::Char('g') => {
+                app.rewind = 2;
+            }
+
+            Key::Char('<') => {
+                if let Some(state) = emulation_state.pop() {
+                    chip8 = state;
+                    app.history.pop_back();
+                }
+                app.memory.clear_written();
+            }
+            Key::Char('>') => {
+                app.stop = None;
+                app.memory.clear_written();
+                emulation_state.push(&mut chip8);
+                app.step(&mut chip8);
+            }
+
+            Key::Char('p') => {
+                app.paused = !app.paused;
+                app.resuming = !app.paused;
+                app.error = None;
+                app.stop = None;
+            }
+
+            // breakpoints
+            Key::Char('b') if app.debug => {
+                app.prompt = Some(Prompt {
+                    kind: PromptKind::Breakpoint,
+                    input: format!("{:#05X}", chip8.program_counter),
+                });
+            }
+            Key::Char('W') if app.debug => {
+                app.prompt = Some(Prompt {
+                    kind: PromptKind::Watchpoint,
+                    input: String::new(),
+                });
+            }
+            Key::Char('B') if app.debug => {
+                app.debugger.clear_breakpoints();
+                app.debugger.clear_watchpoints();
+                app.show_status(
+                    String::from("breakpoints and watchpoints cleared"),
+                    scheduler.frame,
+                );
+            }
+
+            // memory panel
+            Key::Char('m') if app.debug => {
+                app.memory.follow = app.memory.follow.next();
+                let status = match app.memory.follow {
+                    Follow::Pc => "memory follows PC",
+                    Follow::I => "memory follows I",
+                    Follow::Off => "memory stays put",
+                };
+                app.show_status(String::from(status), scheduler.frame);
+            }
+            Key::Char('P') if app.debug => {
+                if app.profiler.is_some() {
+                    app.show_profile = !app.show_profile;
+                } else {
+                    app.show_status(
+                        String::from("start with --profile to profile"),
+                        scheduler.frame,
+                    );
+                }
+            }
+            Key::Char('G') if app.debug => {
+                app.prompt = Some(Prompt {
+                    kind: PromptKind::GoTo,
+                    input: String::new(),
+                });
+            }
+            Key::Up if app.debug => app.memory.scroll(&chip8, -1),
+            Key::Down if app.debug => app.memory.scroll(&chip8, 1),
+            Key::PageUp if app.debug => app.memory.scroll(&chip8, -(app.memory.rows as i64)),
+            Key::PageDown if app.debug => app.memory.scroll(&chip8, app.memory.rows as i64),
+
+            // save states
+            Key::F(n @ 1..=4) => {
+                let status = match app.save_state(&chip8, n) {
+                    Ok(()) => format!("saved slot {}", n),
+                    Err(err) => format!("saving slot {} failed: {}", n, err),
+                };
+                app.show_status(status, scheduler.frame);
+            }
+            Key::F(n @ 5..=8) => {
+                let slot = n - 4;
+                let status = match app.load_state(slot) {
+                    Ok(state) => {
+                        chip8 = state;
+                        app.error = None;
+                        app.history.clear();
+                        format!("loaded slot {}", slot)
+                    }
+                    Err(SaveStateError::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+                        format!("slot {} is empty", slot)
+                    }
+                    Err(err) => format!("loading slot {} failed: {}", slot, err),
+                };
+                app.show_status(status, scheduler.frame);
+            }
+            _ => {}
+        }
+        draw_frame(
+            &mut terminal,
+            &mut duration,
+            &mut app,
+            &chip8,
+            &emulation_state,
+        )?;
+    }
+    buzzer.finish()?;
+    // A long report reads better on an empty screen
+    if app.profiler.is_some() && app.args.profile_report.is_none() {
+        terminal.clear()?;
+        terminal.set_cursor(0, 0)?;
+    }
+    // Give the terminal back before reporting on the movie
+    drop(terminal);
+    if let (Some(tracer), Some(path)) = (app.tracer.take(), &app.args.trace) {
+        tracer
+            .finish()
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+    }
+    if let (Some(coverage), Some(path)) = (&app.coverage, &app.args.coverage) {
+        let mut out = BufWriter::new(File::create(path)?);
+        coverage.write(&mut out)?;
+        out.flush()?;
+    }
+    if let Some(profiler) = &app.profiler {
+        match &app.args.profile_report {
+            Some(path) => {
+                let mut out = BufWriter::new(File::create(path)?);
+                profiler.report(&chip8.mem, &app.symbols, &mut out)?;
+                out.flush()?;
+            }
+            None => profiler.report(&chip8.mem, &app.symbols, &mut io::stdout().lock())?,
+        }
+    }
+    if let (Some(mut movie), Some(path)) = (app.recording.take(), &app.args.record) {
+        movie.finish();
+        let mut out = BufWriter::new(File::create(path)?);
+        movie.write(&mut out)?;
+        out.flush()?;
+        println!(
+            "Recorded {} frames to {}, final state hash {:016x}",
+            movie.frames,
+            path.display(),
+            movie.final_hash
+        );
+    }
+    if let Some(playback) = &app.playback {
+        let movie = &playback.movie;
+        let hash = savestate::state_hash(&chip8);
+        if !playback.finished(scheduler.frame) {
+            println!(
+                "Playback stopped at frame {} of {}, state hash {:016x}",
+                scheduler.frame, movie.frames, hash
+            );
+        } else if hash == movie.final_hash {
+            println!("Final state hash {:016x} matches the recording", hash);
+        } else {
+            println!(
+                "Final state hash {:016x} does not match the recorded {:016x}",
+                hash, movie.final_hash
+            );
+            std::process::exit(1);
         }
     }
     Ok(())
 }
 
+/// Instructions in `seconds` of emulation, `None` if that doesn't fit in a `usize`
+fn rewind_capacity(seconds: u32, cycles_per_frame: u32) -> Option<usize> {
+    (seconds as usize)
+        .checked_mul(FRAME_RATE as usize)?
+        .checked_mul(cycles_per_frame as usize)
+}
+
+/// Parses an address range like `0x200-0x2FF`, or a single address
+fn parse_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = match s.split_once('-') {
+        Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+        None => {
+            let addr = parse_address(s)?;
+            (addr, addr)
+        }
+    };
+    if end < start {
+        return Err(format!("invalid address range '{}'", s));
+    }
+    Ok(start..=end)
+}
+
+/// Keys that would make a movie diverge from the inputs it logs
+fn breaks_movie(key: Key) -> bool {
+    matches!(
+        key,
+        Key::Ctrl('r') | Key::Char('g' | '<' | '>' | 'p') | Key::F(5..=8)
+    )
+}
+
+/// Runs one 60Hz frame worth of instructions, or rewinds as many while `g` is held
+fn run_frame(
+    app: &mut App,
+    chip8: &mut Chip8,
+    emulation_state: &mut Rewind,
+    scheduler: &Scheduler,
+) {
+    if let Some(playback) = &mut app.playback {
+        playback.feed(scheduler.frame - 1, chip8);
+    }
+    if app.rewind > 0 {
+        for _ in 0..scheduler.cycles_per_frame {
+            match emulation_state.pop() {
+                Some(state) => {
+                    *chip8 = state;
+                    app.history.pop_back();
+                }
+                None => break,
+            }
+        }
+    } else if !app.paused {
+        app.memory.clear_written();
+        for _ in 0..scheduler.cycles_per_frame {
+            if app.check_debugger(chip8) {
+                break;
+            }
+            emulation_state.push(chip8);
+            // Read from program counter and execute opcode
+            app.step(chip8);
+            if app.paused || chip8.exited {
+                break;
+            }
+        }
+    }
+    chip8.end_frame();
+    let paused = app.paused;
+    app.call_script(chip8, Hook::Frame(scheduler.frame));
+    if paused && !app.paused {
+        app.resuming = true;
+        app.error = None;
+        app.stop = None;
+    }
+    if matches!(app.status, Some((_, until)) if scheduler.frame >= until) {
+        app.status = None;
+    }
+    if let Some(line) = std::mem::take(&mut app.script_output).pop() {
+        app.show_status(line, scheduler.frame);
+    }
+    // Terminals only report key presses, so let them go after a few frames
+    if scheduler.frame.is_multiple_of(3) {
+        chip8.decay_keys();
+        if app.rewind > 0 {
+            app.rewind -= 1;
+        }
+    }
+}
+
 fn draw_frame(
     term: &mut Terminal<TermionBackend<RawTerminal<io::Stdout>>>,
     duration: &mut Instant,
     app: &mut App,
     chip8: &Chip8,
-    emulation_state: &Vec<Chip8>,
+    emulation_state: &Rewind,
 ) -> Result<(), io::Error> {
     let frame_duration = duration.elapsed().as_millis();
-    let cpu_cycles = emulation_state.len();
-    let playback = if app.paused {
+    let rewind_depth =
+        emulation_state.len() as f64 / (app.args.cycles_per_frame as f64 * FRAME_RATE as f64);
+    let playback = if chip8.exited {
+        "exited"
+    } else if app.error.is_some() {
+        "error"
+    } else if app.paused {
         "paused"
     } else if app.rewind > 0 {
         "<<"
     } else {
         ">"
     };
+    let mut status = app
+        .status
+        .as_ref()
+        .map_or_else(String::new, |(status, _)| format!("[{}]", status));
+    if app.recording.is_some() {
+        status.insert_str(0, "[rec]");
+    } else if app.playback.is_some() {
+        status.insert_str(0, "[play]");
+    }
+    let border_style = if app.flash.get() {
+        Style::default().fg(Color::Yellow)
+    } else {
+        Style::default()
+    };
 
     term.draw(|f| {
         let size = f.size();
         if !app.debug {
             let block = Block::default()
                 .title(format!(
-                    "Chip8 Emulator [{} ms per frame][{} cpu cycles][{}]",
-                    frame_duration, cpu_cycles, playback
+                    "Chip8 Emulator [{} ms per frame][{} cycles, {:.1}s of {}s rewind][{}]{}",
+                    frame_duration,
+                    emulation_state.len(),
+                    rewind_depth,
+                    app.args.rewind_seconds,
+                    playback,
+                    status
                 ))
-                .borders(Borders::ALL);
+                .borders(Borders::ALL)
+                .border_style(border_style);
             f.render_widget(block, size);
         }
         let chunks = Layout::default()
@@ -305,6 +1205,26 @@ fn draw_frame(
 
         let (display, opcodeview) = (chunks[0], chunks[1]);
 
+        let chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .margin(0)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .split(opcodeview);
+
+        let (opcodeview, memoryview) = (chunks[0], chunks[1]);
+
+        // The coverage summary goes under the memory panel
+        let (memoryview, coverageview) = if app.coverage.is_some() {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(0)
+                .constraints([Constraint::Min(0), Constraint::Length(4)].as_ref())
+                .split(memoryview);
+            (chunks[0], Some(chunks[1]))
+        } else {
+            (memoryview, None)
+        };
+
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .margin(0)
@@ -313,6 +1233,14 @@ fn draw_frame(
 
         let (help, stack) = (chunks[0], chunks[1]);
 
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .margin(0)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .split(stack);
+
+        let (breakpoints, stack) = (chunks[0], chunks[1]);
+
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .margin(1)
@@ -336,7 +1264,7 @@ fn draw_frame(
                 Constraint::Percentage(25),
             ])
             .split(row1);
-        let (pad1, pad2, pad3, padC) = (chunks[0], chunks[1], chunks[2], chunks[3]);
+        let (pad1, pad2, pad3, pad_c) = (chunks[0], chunks[1], chunks[2], chunks[3]);
         let chunks = Layout::default()
             .direction(Direction::Horizontal)
             .margin(0)
@@ -347,7 +1275,7 @@ fn draw_frame(
                 Constraint::Percentage(25),
             ])
             .split(row2);
-        let (pad4, pad5, pad6, padD) = (chunks[0], chunks[1], chunks[2], chunks[3]);
+        let (pad4, pad5, pad6, pad_d) = (chunks[0], chunks[1], chunks[2], chunks[3]);
         let chunks = Layout::default()
             .direction(Direction::Horizontal)
             .margin(0)
@@ -358,7 +1286,7 @@ fn draw_frame(
                 Constraint::Percentage(25),
             ])
             .split(row3);
-        let (pad7, pad8, pad9, padE) = (chunks[0], chunks[1], chunks[2], chunks[3]);
+        let (pad7, pad8, pad9, pad_e) = (chunks[0], chunks[1], chunks[2], chunks[3]);
         let chunks = Layout::default()
             .direction(Direction::Horizontal)
             .margin(0)
@@ -369,13 +1297,17 @@ fn draw_frame(
                 Constraint::Percentage(25),
             ])
             .split(row4);
-        let (padA, pad0, padB, padF) = (chunks[0], chunks[1], chunks[2], chunks[3]);
+        let (pad_a, pad0, pad_b, pad_f) = (chunks[0], chunks[1], chunks[2], chunks[3]);
 
         if app.debug {
             let block = Block::default().title("CPU").borders(Borders::ALL);
             f.render_widget(block, registers);
             let text = Paragraph::new(vec![
                 Spans::from(format!("OP: {:#X}", chip8.opcode)),
+                Spans::from(
+                    Instruction::decode(chip8.opcode)
+                        .map_or_else(|| String::from("???"), |i| i.to_string()),
+                ),
                 Spans::from(format!("V0: {:#X}", chip8.vreg[0])),
                 Spans::from(format!("V2: {:#X}", chip8.vreg[2])),
                 Spans::from(format!("V4: {:#X}", chip8.vreg[4])),
@@ -402,19 +1334,131 @@ fn draw_frame(
             ])
             .wrap(Wrap { trim: true });
             f.render_widget(text, registers_right);
-            let block = Block::default().title("Stack").borders(Borders::ALL);
-            f.render_widget(block, stack);
-            f.render_widget(
-                Block::default()
-                    .title("Opcode description")
-                    .borders(Borders::ALL),
-                opcodeview,
-            );
+            let stack_lines = stack_lines(app, chip8);
+            // Keep the innermost calls in view when the list doesn't fit
+            let scroll = stack_lines
+                .len()
+                .saturating_sub(stack.height.saturating_sub(2) as usize);
+            let stack_list = Paragraph::new(stack_lines)
+                .block(
+                    Block::default()
+                        .title(format!(
+                            "Stack {}/{}",
+                            chip8.stack_pointer,
+                            chip8.stack.len()
+                        ))
+                        .borders(Borders::ALL),
+                )
+                .scroll((scroll as u16, 0));
+            f.render_widget(stack_list, stack);
+            let mut description = Vec::new();
+            if let Some(stop) = &app.stop {
+                description.push(Spans::from(Span::styled(
+                    stop.to_string(),
+                    Style::default().fg(Color::Red),
+                )));
+            }
+            // Newest first, so the instruction that just ran is always in view
+            description.extend(app.history.iter().rev().enumerate().map(|(i, line)| {
+                if i == 0 {
+                    Spans::from(Span::styled(
+                        line.clone(),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ))
+                } else {
+                    Spans::from(line.clone())
+                }
+            }));
+            let description = Paragraph::new(description)
+                .block(
+                    Block::default()
+                        .title("Opcode description")
+                        .borders(Borders::ALL),
+                )
+                .wrap(Wrap { trim: true });
+            f.render_widget(description, opcodeview);
+            match &app.profiler {
+                Some(profiler) if app.show_profile => {
+                    let rows = memoryview.height.saturating_sub(2) as usize;
+                    let profile = Paragraph::new(profile_lines(profiler, &app.symbols, rows))
+                        .block(
+                            Block::default()
+                                .title(format!("Profile, {} instructions", profiler.total))
+                                .borders(Borders::ALL),
+                        );
+                    f.render_widget(profile, memoryview);
+                }
+                _ => {
+                    let memory_title = match app.memory.follow {
+                        Follow::Pc => "Memory (PC)",
+                        Follow::I => "Memory (I)",
+                        Follow::Off => "Memory",
+                    };
+                    let memory = app.memory.lines(
+                        chip8,
+                        app.rom_len,
+                        memoryview.width.saturating_sub(2),
+                        memoryview.height.saturating_sub(2) as usize,
+                    );
+                    let memory = Paragraph::new(memory)
+                        .block(Block::default().title(memory_title).borders(Borders::ALL));
+                    f.render_widget(memory, memoryview);
+                }
+            }
+            if let (Some(coverage), Some(coverageview)) = (&app.coverage, coverageview) {
+                let rom = ORIGIN as usize..ORIGIN as usize + app.rom_len;
+                let summary = coverage.summary(rom);
+                let runs = match coverage.runs {
+                    1 => String::new(),
+                    runs => format!(", {} runs", runs),
+                };
+                let coverage = Paragraph::new(coverage_lines(&summary)).block(
+                    Block::default()
+                        .title(format!("Coverage, ROM {} bytes{}", summary.len, runs))
+                        .borders(Borders::ALL),
+                );
+                f.render_widget(coverage, coverageview);
+            }
+            let mut breakpoint_lines: Vec<Spans> = app
+                .debugger
+                .breakpoints()
+                .map(|addr| {
+                    let line = format!("{:#05X}", addr);
+                    if addr == chip8.program_counter {
+                        Spans::from(Span::styled(line, Style::default().fg(Color::Red)))
+                    } else {
+                        Spans::from(line)
+                    }
+                })
+                .collect();
+            breakpoint_lines.extend(app.debugger.watchpoints().iter().map(|watchpoint| {
+                let line = format!("watch {}", watchpoint);
+                match &app.stop {
+                    Some(Stop::Watchpoint {
+                        watchpoint: hit, ..
+                    }) if hit == watchpoint => {
+                        Spans::from(Span::styled(line, Style::default().fg(Color::Red)))
+                    }
+                    _ => Spans::from(line),
+                }
+            }));
+            let breakpoint_list = Paragraph::new(breakpoint_lines)
+                .block(Block::default().title("Breakpoints").borders(Borders::ALL));
+            f.render_widget(breakpoint_list, breakpoints);
             let help_block = Block::default().title("Help").borders(Borders::ALL);
             let help_text = Paragraph::new(vec![
                 Spans::from("ctrl+c -> exit emulator"),
                 Spans::from("ctrl+d -> exit debug"),
                 Spans::from("ctrl+o -> show original controls"),
+                Spans::from("F1-F4 -> save to slot 1-4"),
+                Spans::from("F5-F8 -> load slot 1-4"),
+                Spans::from("b -> toggle breakpoint, W -> toggle watchpoint"),
+                Spans::from("B -> clear breakpoints and watchpoints"),
+                Spans::from("m -> memory follows PC/I/nothing, G -> go to address"),
+                Spans::from("up/down/pgup/pgdn -> scroll memory"),
+                Spans::from("P -> profile/memory panel"),
+                Spans::from("p -> pause/resume, > -> step"),
+                Spans::from(status.clone()),
             ])
             .wrap(Wrap { trim: true });
             f.render_widget(help_text.block(help_block), help);
@@ -425,19 +1469,19 @@ fn draw_frame(
             f.render_widget(render_key_widget('1', app, chip8), pad1);
             f.render_widget(render_key_widget('2', app, chip8), pad2);
             f.render_widget(render_key_widget('3', app, chip8), pad3);
-            f.render_widget(render_key_widget('C', app, chip8), padC);
+            f.render_widget(render_key_widget('C', app, chip8), pad_c);
             f.render_widget(render_key_widget('4', app, chip8), pad4);
             f.render_widget(render_key_widget('5', app, chip8), pad5);
             f.render_widget(render_key_widget('6', app, chip8), pad6);
-            f.render_widget(render_key_widget('D', app, chip8), padD);
+            f.render_widget(render_key_widget('D', app, chip8), pad_d);
             f.render_widget(render_key_widget('7', app, chip8), pad7);
             f.render_widget(render_key_widget('8', app, chip8), pad8);
             f.render_widget(render_key_widget('9', app, chip8), pad9);
-            f.render_widget(render_key_widget('E', app, chip8), padE);
-            f.render_widget(render_key_widget('A', app, chip8), padA);
+            f.render_widget(render_key_widget('E', app, chip8), pad_e);
+            f.render_widget(render_key_widget('A', app, chip8), pad_a);
             f.render_widget(render_key_widget('0', app, chip8), pad0);
-            f.render_widget(render_key_widget('B', app, chip8), padB);
-            f.render_widget(render_key_widget('F', app, chip8), padF);
+            f.render_widget(render_key_widget('B', app, chip8), pad_b);
+            f.render_widget(render_key_widget('F', app, chip8), pad_f);
         }
         if size.height < 18 || size.width < 30 {
             let block = Block::default()
@@ -445,7 +1489,11 @@ fn draw_frame(
                 .borders(Borders::ALL);
             let paragraph = Paragraph::new(vec![
                 Spans::from(format!("{}x{} is too low!", size.width, size.height)),
-                Spans::from("Chip8 display is 64×32 px"),
+                Spans::from(format!(
+                    "Chip8 display is {}×{} px",
+                    chip8.gfx.width(),
+                    chip8.gfx.height()
+                )),
             ])
             .block(block)
             .wrap(Wrap { trim: true });
@@ -456,11 +1504,16 @@ fn draw_frame(
                 .paint(|ctx| {
                     ctx.draw(&chip8.gfx);
                 })
-                .x_bounds([0.0, 64.0])
-                .y_bounds([0.0, 32.0]);
+                .x_bounds([0.0, chip8.gfx.width() as f64])
+                .y_bounds([0.0, chip8.gfx.height() as f64]);
 
             let canvas = if app.debug {
-                canvas.block(Block::default().title("Display").borders(Borders::ALL))
+                canvas.block(
+                    Block::default()
+                        .title("Display")
+                        .borders(Borders::ALL)
+                        .border_style(border_style),
+                )
             } else {
                 canvas
             };
@@ -479,12 +1532,156 @@ fn draw_frame(
                 },
             )
         }
+        if let Some(prompt) = &app.prompt {
+            let popup = centered_rect(40, 20, f.size());
+            let paragraph = Paragraph::new(vec![
+                Spans::from(format!("{}_", prompt.input)),
+                Spans::from(""),
+                Spans::from("enter -> confirm, esc -> cancel"),
+            ])
+            .block(
+                Block::default()
+                    .title(prompt.kind.title())
+                    .borders(Borders::ALL),
+            );
+            f.render_widget(Clear, popup);
+            f.render_widget(paragraph, popup);
+        }
+        if let Some(err) = &app.error {
+            let block = Block::default()
+                .title("Execution error")
+                .borders(Borders::ALL);
+            let popup = centered_rect(60, 30, f.size());
+            let paragraph = Paragraph::new(vec![
+                Spans::from(err.to_string()),
+                Spans::from(""),
+                Spans::from("p -> resume, < -> step back, ctrl+r -> reset"),
+            ])
+            .block(block)
+            .style(Style::default().fg(Color::Red))
+            .wrap(Wrap { trim: true });
+            f.render_widget(Clear, popup);
+            f.render_widget(paragraph, popup);
+        }
         *duration = Instant::now();
     })?;
     Ok(())
 }
 
-fn make_debug_layout() {}
+/// Return addresses from the outermost call in, with the calling subroutine
+/// when symbols are loaded, and a warning as the stack fills up
+fn stack_lines(app: &App, chip8: &Chip8) -> Vec<Spans<'static>> {
+    let depth = chip8.stack_pointer as usize;
+    let mut lines: Vec<Spans> = chip8.stack[..depth.min(chip8.stack.len())]
+        .iter()
+        .enumerate()
+        .map(|(i, &ret)| {
+            if app.symbols.is_empty() {
+                Spans::from(format!("{:2}: {:#05X}", i, ret))
+            } else {
+                // The CALL is the instruction before the return address
+                let caller = app.symbols.describe(ret.wrapping_sub(2));
+                Spans::from(format!("{:2}: {:#05X} from {}", i, ret, caller))
+            }
+        })
+        .collect();
+    if !app.symbols.is_empty() {
+        lines.push(Spans::from(format!(
+            "pc: {:#05X} in {}",
+            chip8.program_counter,
+            app.symbols.describe(chip8.program_counter)
+        )));
+    }
+    let free = chip8.stack.len().saturating_sub(depth);
+    if free == 0 {
+        lines.push(Spans::from(Span::styled(
+            "stack full, CALL overflows",
+            Style::default().fg(Color::Red),
+        )));
+    } else if free <= 4 {
+        lines.push(Spans::from(Span::styled(
+            format!("{} entries left", free),
+            Style::default().fg(Color::Yellow),
+        )));
+    }
+    lines
+}
+
+/// Shares of the ROM executed, read, written and never touched
+fn coverage_lines(summary: &Summary) -> Vec<Spans<'static>> {
+    vec![
+        Spans::from(format!(
+            "run {:.1}%  read {:.1}%",
+            summary.percent(summary.executed),
+            summary.percent(summary.read)
+        )),
+        Spans::from(format!(
+            "written {:.1}%  untouched {:.1}%",
+            summary.percent(summary.written),
+            summary.percent(summary.untouched)
+        )),
+    ]
+}
+
+/// The hottest addresses, subroutines and opcode classes that fit in `rows`
+fn profile_lines(profiler: &Profiler, symbols: &Symbols, rows: usize) -> Vec<Spans<'static>> {
+    let heading = |text: &'static str| {
+        Spans::from(Span::styled(
+            text,
+            Style::default().add_modifier(Modifier::BOLD),
+        ))
+    };
+    // Half the space for addresses, a quarter each for the rest
+    let entries = rows.saturating_sub(3);
+    let (addrs, subroutines) = (entries / 2, entries / 4);
+    let classes = entries - addrs - subroutines;
+
+    let mut lines = vec![heading("Hot addresses")];
+    lines.extend(
+        profiler
+            .hot_addresses(addrs)
+            .into_iter()
+            .map(|(addr, count)| {
+                let name = if symbols.is_empty() {
+                    String::new()
+                } else {
+                    symbols.describe(addr)
+                };
+                Spans::from(format!(
+                    "{:5.1}% {:#05X} {}",
+                    profiler.percent(count),
+                    addr,
+                    name
+                ))
+            }),
+    );
+    lines.push(heading("Subroutines, own and total"));
+    lines.extend(
+        profiler
+            .subroutines()
+            .into_iter()
+            .take(subroutines)
+            .map(|(entry, sub)| {
+                Spans::from(format!(
+                    "{:5.1}% {:5.1}% {}",
+                    profiler.percent(sub.own),
+                    profiler.percent(sub.total),
+                    symbols.describe(entry)
+                ))
+            }),
+    );
+    lines.push(heading("Opcode classes"));
+    lines.extend(
+        profiler
+            .classes()
+            .into_iter()
+            .take(classes)
+            .map(|(class, count)| {
+                Spans::from(format!("{:5.1}% {}", profiler.percent(count), class))
+            }),
+    );
+    lines
+}
 
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
     let popup_layout = Layout::default()
@@ -0,0 +1,164 @@
+use std::{collections::BTreeSet, ops::Range};
+
+use chipterm::access::{AccessKind, Target};
+use chipterm::disasm::ORIGIN;
+use chipterm::Chip8;
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+
+/// Small and big font sprites, as `Chip8::new` loads them
+const FONT: Range<usize> = 0x050..0x104;
+
+/// What the memory panel keeps in view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Follow {
+    Pc,
+    I,
+    /// Stays where it was scrolled to
+    Off,
+}
+
+impl Follow {
+    pub fn next(self) -> Self {
+        match self {
+            Follow::Pc => Follow::I,
+            Follow::I => Follow::Off,
+            Follow::Off => Follow::Pc,
+        }
+    }
+}
+
+/// State of the hex/ASCII memory panel in the debug view.
+#[derive(Debug, Clone)]
+pub struct MemoryView {
+    /// First address shown when not following
+    pub addr: u16,
+    pub follow: Follow,
+    /// Panel size as last drawn, for scrolling
+    pub rows: usize,
+    pub bytes_per_row: usize,
+    /// Bytes written by the last step, or the last frame while running
+    written: BTreeSet<u16>,
+}
+
+impl Default for MemoryView {
+    fn default() -> Self {
+        MemoryView {
+            addr: ORIGIN,
+            follow: Follow::Pc,
+            rows: 1,
+            bytes_per_row: 8,
+            written: BTreeSet::new(),
+        }
+    }
+}
+
+impl MemoryView {
+    /// Marks the memory the step that just ran wrote
+    pub fn after_step(&mut self, chip8: &Chip8) {
+        for access in chip8.accesses() {
+            if let (AccessKind::Write, Target::Mem { first, last }) = (access.kind, access.target) {
+                self.written.extend(first..=last);
+            }
+        }
+    }
+
+    pub fn clear_written(&mut self) {
+        self.written.clear();
+    }
+
+    /// Moves the view by `rows` rows and stops following
+    pub fn scroll(&mut self, chip8: &Chip8, rows: i64) {
+        let bytes_per_row = self.bytes_per_row;
+        let top = self.top(chip8, bytes_per_row) as i64 + rows * bytes_per_row as i64;
+        let last_row = (chip8.mem.len() - 1) / bytes_per_row * bytes_per_row;
+        self.addr = top.clamp(0, last_row as i64) as u16;
+        self.follow = Follow::Off;
+    }
+
+    /// Shows `addr` and stops following
+    pub fn go_to(&mut self, addr: u16) {
+        self.addr = addr;
+        self.follow = Follow::Off;
+    }
+
+    /// First address of the top row, a row above the followed address
+    fn top(&self, chip8: &Chip8, bytes_per_row: usize) -> usize {
+        let addr = match self.follow {
+            Follow::Pc => (chip8.program_counter as usize).saturating_sub(bytes_per_row),
+            Follow::I => (chip8.ireg as usize).saturating_sub(bytes_per_row),
+            Follow::Off => self.addr as usize,
+        };
+        addr.min(chip8.mem.len() - 1) / bytes_per_row * bytes_per_row
+    }
+
+    /// Hex and ASCII lines filling `width` by `rows`. The font and the
+    /// `rom_len` bytes of program are colored, written bytes stand out, the
+    /// PC is reversed and I underlined.
+    pub fn lines(
+        &mut self,
+        chip8: &Chip8,
+        rom_len: usize,
+        width: u16,
+        rows: usize,
+    ) -> Vec<Spans<'static>> {
+        let bytes_per_row = bytes_per_row(width);
+        self.rows = rows.max(1);
+        self.bytes_per_row = bytes_per_row;
+        let program = ORIGIN as usize..ORIGIN as usize + rom_len;
+        let pc = chip8.program_counter as usize;
+        let style = |addr: usize| {
+            let mut style = if self.written.contains(&(addr as u16)) {
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+            } else if FONT.contains(&addr) {
+                Style::default().fg(Color::Cyan)
+            } else if program.contains(&addr) {
+                Style::default().fg(Color::Green)
+            } else {
+                Style::default()
+            };
+            if addr == pc || addr == pc + 1 {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+            if addr == chip8.ireg as usize {
+                style = style.add_modifier(Modifier::UNDERLINED);
+            }
+            style
+        };
+
+        let top = self.top(chip8, bytes_per_row);
+        (top..chip8.mem.len())
+            .step_by(bytes_per_row)
+            .take(rows)
+            .map(|start| {
+                let end = (start + bytes_per_row).min(chip8.mem.len());
+                let mut spans = vec![Span::raw(format!("{:04X}:", start))];
+                for addr in start..end {
+                    spans.push(Span::raw(" "));
+                    spans.push(Span::styled(
+                        format!("{:02X}", chip8.mem[addr]),
+                        style(addr),
+                    ));
+                }
+                spans.push(Span::raw("  "));
+                for addr in start..end {
+                    let byte = chip8.mem[addr];
+                    let c = if byte.is_ascii_graphic() || byte == b' ' {
+                        byte as char
+                    } else {
+                        '.'
+                    };
+                    spans.push(Span::styled(c.to_string(), style(addr)));
+                }
+                Spans::from(spans)
+            })
+            .collect()
+    }
+}
+
+/// Widest power of two row that fits `width` columns, at most 16 bytes
+fn bytes_per_row(width: u16) -> usize {
+    // "0200:" plus " XX" and an ASCII column per byte
+    let fits = |n: usize| 5 + n * 3 + 2 + n <= width as usize;
+    [16, 8, 4].iter().copied().find(|&n| fits(n)).unwrap_or(2)
+}
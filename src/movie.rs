@@ -0,0 +1,297 @@
+//! Input movies: key presses logged against the frame number, replayed deterministically.
+//!
+//! A movie is a text file:
+//!
+//! ```text
+//! chipterm-movie 1
+//! rom 510ced63295af96f
+//! platform xochip
+//! quirks shift_vy=1 load_store=2 jump_vx=0 vf_reset=0 display_wait=0 clip=0
+//! seed 42
+//! cycles-per-frame 12
+//! 120 press 5
+//! 135 press A
+//! end 3600 2d06800538d394c2
+//! ```
+//!
+//! Event lines are `<frame> press <key>`, where the frame is the number of
+//! 60Hz frames run before the key was pressed. Terminals don't report key
+//! releases, so there are no release events. The `end` line holds the length
+//! of the movie in frames and the hash of the final state.
+
+use std::{
+    error, fmt,
+    io::{self, BufRead, Write},
+};
+
+use crate::core::Chip8;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::savestate;
+
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Press(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    /// Frames run before the input arrived
+    pub frame: u64,
+    pub input: Input,
+}
+
+/// Everything needed to replay a run: the machine setup and the inputs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub cycles_per_frame: u32,
+    /// Inputs in the order they arrived
+    pub events: Vec<Event>,
+    /// Length in frames, extended as each recorded frame ends
+    pub frames: u64,
+    /// `savestate::state_hash` of the machine after the last frame
+    pub final_hash: u64,
+}
+
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::Io(err) => write!(f, "{}", err),
+            MovieError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl error::Error for MovieError {}
+
+impl From<io::Error> for MovieError {
+    fn from(err: io::Error) -> Self {
+        MovieError::Io(err)
+    }
+}
+
+impl Movie {
+    /// An empty movie for a machine set up like `chip8`
+    pub fn new(chip8: &Chip8, rom_hash: u64, cycles_per_frame: u32) -> Self {
+        Movie {
+            rom_hash,
+            platform: chip8.platform,
+            quirks: chip8.quirks,
+            seed: chip8.seed,
+            cycles_per_frame,
+            events: Vec::new(),
+            frames: 0,
+            final_hash: savestate::state_hash(chip8),
+        }
+    }
+
+    /// Logs an input that arrived after `frame` frames
+    pub fn record(&mut self, frame: u64, input: Input) {
+        self.events.push(Event { frame, input });
+    }
+
+    /// Extends the movie to `frames` frames, with `chip8` as the state after the last one
+    pub fn end_frame(&mut self, frames: u64, chip8: &Chip8) {
+        self.frames = frames;
+        self.final_hash = savestate::state_hash(chip8);
+    }
+
+    /// Ends the movie at the last frame passed to `end_frame`. Inputs that
+    /// arrived after it would never be replayed, so they are dropped.
+    pub fn finish(&mut self) {
+        let frames = self.frames;
+        self.events.retain(|event| event.frame < frames);
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let quirks: Vec<String> = Quirks::NAMES
+            .iter()
+            .zip(self.quirks.values().iter())
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        writeln!(out, "chipterm-movie {}", VERSION)?;
+        writeln!(out, "rom {:016x}", self.rom_hash)?;
+        writeln!(out, "platform {}", self.platform)?;
+        writeln!(out, "quirks {}", quirks.join(" "))?;
+        writeln!(out, "seed {}", self.seed)?;
+        writeln!(out, "cycles-per-frame {}", self.cycles_per_frame)?;
+        for event in &self.events {
+            match event.input {
+                Input::Press(key) => writeln!(out, "{} press {:X}", event.frame, key)?,
+            }
+        }
+        writeln!(out, "end {} {:016x}", self.frames, self.final_hash)
+    }
+
+    pub fn read(input: impl BufRead) -> Result<Movie, MovieError> {
+        let mut lines = input.lines();
+        let mut n = 0;
+        let mut next_line = |what: &str| -> Result<(usize, String), MovieError> {
+            n += 1;
+            match lines.next() {
+                Some(line) => Ok((n, line?)),
+                None => Err(parse_error(n, format!("missing {}", what))),
+            }
+        };
+
+        let (n, line) = next_line("header")?;
+        let version = field(n, &line, "chipterm-movie")?;
+        if version != VERSION.to_string() {
+            return Err(parse_error(
+                n,
+                format!(
+                    "movie version {} is not supported, expected version {}",
+                    version, VERSION
+                ),
+            ));
+        }
+        let (n, line) = next_line("rom")?;
+        let rom_hash = parse_hex(n, field(n, &line, "rom")?)?;
+        let (n, line) = next_line("platform")?;
+        let platform = field(n, &line, "platform")?
+            .parse()
+            .map_err(|err| parse_error(n, err))?;
+        let (n, line) = next_line("quirks")?;
+        let quirks = parse_quirks(n, field(n, &line, "quirks")?)?;
+        let (n, line) = next_line("seed")?;
+        let seed = parse_dec(n, field(n, &line, "seed")?)?;
+        let (n, line) = next_line("cycles-per-frame")?;
+        let cycles_per_frame = parse_dec(n, field(n, &line, "cycles-per-frame")?)?;
+
+        let mut movie = Movie {
+            rom_hash,
+            platform,
+            quirks,
+            seed,
+            cycles_per_frame,
+            events: Vec::new(),
+            frames: 0,
+            final_hash: 0,
+        };
+        loop {
+            let (n, line) = next_line("end line")?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                ["end", frames, hash] => {
+                    movie.frames = parse_dec(n, frames)?;
+                    movie.final_hash = parse_hex(n, hash)?;
+                    return Ok(movie);
+                }
+                [frame, kind, key] => {
+                    let frame = parse_dec(n, frame)?;
+                    let key = u8::from_str_radix(key, 16)
+                        .ok()
+                        .filter(|&key| key < 16)
+                        .ok_or_else(|| parse_error(n, format!("bad key '{}'", key)))?;
+                    let input = match kind {
+                        "press" => Input::Press(key),
+                        _ => return Err(parse_error(n, format!("unknown input '{}'", kind))),
+                    };
+                    if movie.events.last().is_some_and(|last| last.frame > frame) {
+                        return Err(parse_error(n, "events are out of order"));
+                    }
+                    movie.events.push(Event { frame, input });
+                }
+                _ => return Err(parse_error(n, format!("unexpected '{}'", line))),
+            }
+        }
+    }
+}
+
+/// Feeds the inputs of a movie back in at the frames they were recorded at.
+#[derive(Debug, Clone)]
+pub struct Playback {
+    pub movie: Movie,
+    next: usize,
+}
+
+impl Playback {
+    pub fn new(movie: Movie) -> Self {
+        Playback { movie, next: 0 }
+    }
+
+    /// A machine set up the way the movie was recorded, before the ROM is loaded
+    pub fn chip8(&self) -> Chip8 {
+        Chip8::new(self.movie.platform, self.movie.quirks, self.movie.seed)
+    }
+
+    /// Applies every input that arrived after `frame` frames
+    pub fn feed(&mut self, frame: u64, chip8: &mut Chip8) {
+        while let Some(event) = self.movie.events.get(self.next) {
+            if event.frame > frame {
+                break;
+            }
+            match event.input {
+                Input::Press(key) => chip8.press_key(key),
+            }
+            self.next += 1;
+        }
+    }
+
+    /// True once `frame` frames cover the whole movie
+    pub fn finished(&self, frame: u64) -> bool {
+        frame >= self.movie.frames
+    }
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> MovieError {
+    MovieError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+/// The value of a `<name> <value>` header line
+fn field<'a>(n: usize, line: &'a str, name: &str) -> Result<&'a str, MovieError> {
+    match line.split_once(' ') {
+        Some((key, value)) if key == name => Ok(value.trim()),
+        _ => Err(parse_error(n, format!("expected '{}'", name))),
+    }
+}
+
+fn parse_dec<T: std::str::FromStr>(n: usize, s: &str) -> Result<T, MovieError> {
+    s.parse()
+        .map_err(|_| parse_error(n, format!("bad number '{}'", s)))
+}
+
+fn parse_hex(n: usize, s: &str) -> Result<u64, MovieError> {
+    u64::from_str_radix(s, 16).map_err(|_| parse_error(n, format!("bad hash '{}'", s)))
+}
+
+fn parse_quirks(n: usize, s: &str) -> Result<Quirks, MovieError> {
+    let mut values = [0; 6];
+    let mut seen = [false; 6];
+    for setting in s.split_whitespace() {
+        let bad = || parse_error(n, format!("bad quirk '{}'", setting));
+        let (name, value) = setting.split_once('=').ok_or_else(bad)?;
+        let i = Quirks::NAMES
+            .iter()
+            .position(|&known| known == name)
+            .ok_or_else(|| parse_error(n, format!("unknown quirk '{}'", name)))?;
+        if seen[i] {
+            return Err(parse_error(n, format!("quirk '{}' is set twice", name)));
+        }
+        seen[i] = true;
+        values[i] = value.parse().map_err(|_| bad())?;
+    }
+    if let Some(i) = seen.iter().position(|&seen| !seen) {
+        return Err(parse_error(
+            n,
+            format!("missing quirk '{}'", Quirks::NAMES[i]),
+        ));
+    }
+    Quirks::from_values(values)
+        .map_err(|name| parse_error(n, format!("bad value for quirk '{}'", name)))
+}
@@ -0,0 +1,64 @@
+use std::{fmt, str::FromStr};
+
+use crate::quirks::Preset;
+
+/// The CHIP-8 dialect a ROM is written for.
+///
+/// SUPER-CHIP instructions don't collide with anything in the original set, so
+/// they are understood on every platform. XO-CHIP reuses encodings that plain
+/// CHIP-8 interpreters treat differently and needs its own mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub const NAMES: &'static [&'static str] = &["chip8", "schip", "xochip"];
+
+    /// Size of the addressable memory in bytes
+    pub fn mem_size(self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => 4096,
+            Platform::XoChip => 65536,
+        }
+    }
+
+    /// Quirk preset used when none is given on the command line
+    pub fn default_preset(self) -> Preset {
+        match self {
+            Platform::Chip8 => Preset::Modern,
+            Platform::SuperChip => Preset::Schip,
+            Platform::XoChip => Preset::XoChip,
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Ok(Platform::Chip8),
+            "schip" | "superchip" => Ok(Platform::SuperChip),
+            "xochip" | "xo-chip" => Ok(Platform::XoChip),
+            _ => Err(format!(
+                "unknown platform '{}', expected one of: {}",
+                s,
+                Platform::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -0,0 +1,181 @@
+//! Where the cycles go: executions per address, per opcode class and per
+//! subroutine.
+//!
+//! Subroutines are told apart by following 2NNN calls and 00EE returns. Each
+//! instruction counts towards the subroutine it ran in (its own cycles) and
+//! towards every subroutine on the call stack (its total cycles). Code outside
+//! any call belongs to the program entry point at 0x200.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, Write},
+};
+
+use crate::core::Chip8;
+use crate::disasm::ORIGIN;
+use crate::instruction::Instruction;
+use crate::symbols::Symbols;
+
+/// Hot addresses listed in `report`
+const REPORT_ADDRESSES: usize = 50;
+
+/// Cycles spent in a subroutine
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Subroutine {
+    pub calls: u64,
+    /// Instructions run in the subroutine itself
+    pub own: u64,
+    /// Instructions run in it and in the subroutines it called
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    /// Instructions recorded
+    pub total: u64,
+    addrs: HashMap<u16, u64>,
+    classes: HashMap<&'static str, u64>,
+    subroutines: BTreeMap<u16, Subroutine>,
+    /// Entry points of the subroutines being run, outermost first
+    calls: Vec<u16>,
+    /// Subroutines already counted by `record`, kept to reuse its allocation
+    counted: Vec<u16>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    /// Counts the instruction at `pc` that `chip8` just executed
+    pub fn record(&mut self, pc: u16, chip8: &Chip8) {
+        self.total += 1;
+        *self.addrs.entry(pc).or_insert(0) += 1;
+        let class = Instruction::decode(chip8.opcode).map_or("????", |i| i.class());
+        *self.classes.entry(class).or_insert(0) += 1;
+
+        let current = self.calls.last().copied().unwrap_or(ORIGIN);
+        self.subroutines.entry(current).or_default().own += 1;
+        self.counted.clear();
+        for entry in std::iter::once(ORIGIN).chain(self.calls.iter().copied()) {
+            // Recursive calls only count once
+            if !self.counted.contains(&entry) {
+                self.counted.push(entry);
+                self.subroutines.entry(entry).or_default().total += 1;
+            }
+        }
+
+        // Follow the stack, which also copes with loaded states and resets
+        let depth = chip8.stack_pointer as usize;
+        self.calls.truncate(depth);
+        if depth > self.calls.len() {
+            let entry = chip8.program_counter;
+            self.subroutines.entry(entry).or_default().calls += 1;
+            self.calls.resize(depth, entry);
+        }
+    }
+
+    /// The `n` most executed addresses and their counts, most executed first
+    pub fn hot_addresses(&self, n: usize) -> Vec<(u16, u64)> {
+        let mut addrs: Vec<(u16, u64)> = self.addrs.iter().map(|(&a, &c)| (a, c)).collect();
+        addrs.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        addrs.truncate(n);
+        addrs
+    }
+
+    /// Executions per opcode class, most executed first
+    pub fn classes(&self) -> Vec<(&'static str, u64)> {
+        let mut classes: Vec<(&'static str, u64)> =
+            self.classes.iter().map(|(&c, &n)| (c, n)).collect();
+        classes.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        classes
+    }
+
+    /// Subroutines by entry point, the most total cycles first
+    pub fn subroutines(&self) -> Vec<(u16, Subroutine)> {
+        let mut subroutines: Vec<(u16, Subroutine)> =
+            self.subroutines.iter().map(|(&a, &s)| (a, s)).collect();
+        subroutines.sort_by(|a, b| b.1.total.cmp(&a.1.total).then(a.0.cmp(&b.0)));
+        subroutines
+    }
+
+    /// Share of all recorded instructions, in percent
+    pub fn percent(&self, count: u64) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / self.total as f64
+        }
+    }
+
+    /// Writes the hot addresses with the instructions now in `mem`, the
+    /// subroutines and the opcode classes
+    pub fn report(&self, mem: &[u8], symbols: &Symbols, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "Profile of {} instructions", self.total)?;
+
+        writeln!(out)?;
+        writeln!(out, "Hot addresses")?;
+        writeln!(out, "{:>12} {:>6}  address  instruction", "count", "%")?;
+        for (addr, count) in self.hot_addresses(REPORT_ADDRESSES) {
+            let opcode = match (mem.get(addr as usize), mem.get(addr as usize + 1)) {
+                (Some(&hi), Some(&lo)) => (hi as u16) << 8 | lo as u16,
+                _ => 0,
+            };
+            let instruction =
+                Instruction::decode(opcode).map_or_else(|| String::from("???"), |i| i.to_string());
+            writeln!(
+                out,
+                "{:>12} {:>5.1}%  {:#06X}   {:<16} {}",
+                count,
+                self.percent(count),
+                addr,
+                instruction,
+                label(symbols, addr)
+            )?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "Subroutines")?;
+        writeln!(
+            out,
+            "{:>12} {:>6} {:>12} {:>6} {:>8}  entry",
+            "own", "%", "total", "%", "calls"
+        )?;
+        for (entry, sub) in self.subroutines() {
+            writeln!(
+                out,
+                "{:>12} {:>5.1}% {:>12} {:>5.1}% {:>8}  {:#06X} {}",
+                sub.own,
+                self.percent(sub.own),
+                sub.total,
+                self.percent(sub.total),
+                sub.calls,
+                entry,
+                label(symbols, entry)
+            )?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "Opcode classes")?;
+        writeln!(out, "{:>12} {:>6}  class", "count", "%")?;
+        for (class, count) in self.classes() {
+            writeln!(
+                out,
+                "{:>12} {:>5.1}%  {}",
+                count,
+                self.percent(count),
+                class
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The symbol at or before `addr`, empty without symbols
+fn label(symbols: &Symbols, addr: u16) -> String {
+    if symbols.lookup(addr).is_some() {
+        symbols.describe(addr)
+    } else {
+        String::new()
+    }
+}
@@ -0,0 +1,202 @@
+use std::{fmt, str::FromStr};
+
+/// How FX55/FX65 move I after dumping or loading registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStore {
+    /// I is left untouched (SCHIP 1.1).
+    Unchanged,
+    /// I is incremented by X (CHIP-48).
+    IncrementX,
+    /// I is incremented by X + 1 (COSMAC VIP).
+    IncrementXPlusOne,
+}
+
+/// Interpretation of the opcodes that differ between CHIP-8 implementations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VY into VX instead of shifting VX in place.
+    pub shift_vy: bool,
+    /// What FX55/FX65 do to I.
+    pub load_store: LoadStore,
+    /// BNNN is read as BXNN and jumps to XNN + VX instead of NNN + V0.
+    pub jump_vx: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0.
+    pub vf_reset: bool,
+    /// DXYN waits for the next 60Hz vertical blank before drawing.
+    pub display_wait: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around.
+    pub clip: bool,
+}
+
+impl Quirks {
+    /// The settings in the order `values` lists them, as movies name them.
+    pub const NAMES: [&'static str; 6] = [
+        "shift_vy",
+        "load_store",
+        "jump_vx",
+        "vf_reset",
+        "display_wait",
+        "clip",
+    ];
+
+    /// The settings as numbers in `NAMES` order, the form save states and movies store.
+    pub fn values(&self) -> [u8; 6] {
+        let load_store = match self.load_store {
+            LoadStore::Unchanged => 0,
+            LoadStore::IncrementX => 1,
+            LoadStore::IncrementXPlusOne => 2,
+        };
+        [
+            self.shift_vy as u8,
+            load_store,
+            self.jump_vx as u8,
+            self.vf_reset as u8,
+            self.display_wait as u8,
+            self.clip as u8,
+        ]
+    }
+
+    /// Quirks back from `values`, or the name of the first setting out of range.
+    pub fn from_values(values: [u8; 6]) -> Result<Self, &'static str> {
+        let flag = |i: usize| match values[i] {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Quirks::NAMES[i]),
+        };
+        Ok(Quirks {
+            shift_vy: flag(0)?,
+            load_store: match values[1] {
+                0 => LoadStore::Unchanged,
+                1 => LoadStore::IncrementX,
+                2 => LoadStore::IncrementXPlusOne,
+                _ => return Err(Quirks::NAMES[1]),
+            },
+            jump_vx: flag(2)?,
+            vf_reset: flag(3)?,
+            display_wait: flag(4)?,
+            clip: flag(5)?,
+        })
+    }
+
+    /// Original COSMAC VIP interpreter.
+    pub fn vip() -> Self {
+        Quirks {
+            shift_vy: true,
+            load_store: LoadStore::IncrementXPlusOne,
+            jump_vx: false,
+            vf_reset: true,
+            display_wait: true,
+            clip: true,
+        }
+    }
+
+    /// CHIP-48 on the HP-48.
+    pub fn chip48() -> Self {
+        Quirks {
+            shift_vy: false,
+            load_store: LoadStore::IncrementX,
+            jump_vx: true,
+            vf_reset: false,
+            display_wait: false,
+            clip: true,
+        }
+    }
+
+    /// SUPER-CHIP 1.1.
+    pub fn schip() -> Self {
+        Quirks {
+            shift_vy: false,
+            load_store: LoadStore::Unchanged,
+            jump_vx: true,
+            vf_reset: false,
+            display_wait: false,
+            clip: true,
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo.
+    pub fn xochip() -> Self {
+        Quirks {
+            shift_vy: true,
+            load_store: LoadStore::IncrementXPlusOne,
+            jump_vx: false,
+            vf_reset: false,
+            display_wait: false,
+            clip: false,
+        }
+    }
+
+    /// What most modern interpreters do, and what chipterm has always done.
+    pub fn modern() -> Self {
+        Quirks {
+            shift_vy: false,
+            load_store: LoadStore::Unchanged,
+            jump_vx: false,
+            vf_reset: false,
+            display_wait: false,
+            clip: true,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::modern()
+    }
+}
+
+/// Named quirk presets selectable from the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    Vip,
+    Chip48,
+    Schip,
+    XoChip,
+    Modern,
+}
+
+impl Preset {
+    pub const NAMES: &'static [&'static str] = &["vip", "chip48", "schip", "xochip", "modern"];
+
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Preset::Vip => Quirks::vip(),
+            Preset::Chip48 => Quirks::chip48(),
+            Preset::Schip => Quirks::schip(),
+            Preset::XoChip => Quirks::xochip(),
+            Preset::Modern => Quirks::modern(),
+        }
+    }
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac" => Ok(Preset::Vip),
+            "chip48" | "chip-48" => Ok(Preset::Chip48),
+            "schip" | "superchip" => Ok(Preset::Schip),
+            "xochip" | "xo-chip" | "octo" => Ok(Preset::XoChip),
+            "modern" => Ok(Preset::Modern),
+            _ => Err(format!(
+                "unknown quirk preset '{}', expected one of: {}",
+                s,
+                Preset::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Preset::Vip => "vip",
+            Preset::Chip48 => "chip48",
+            Preset::Schip => "schip",
+            Preset::XoChip => "xochip",
+            Preset::Modern => "modern",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::VecDeque;
+
+use rand_chacha::ChaCha8Rng;
+
+use crate::core::{Chip8, Dirty};
+
+/// Steps between two full copies of the machine.
+const KEYFRAME_INTERVAL: usize = 1024;
+/// Memory is compared in blocks this big before looking at bytes, when all of it may have changed.
+const BLOCK: usize = 64;
+
+/// Bounded history of machine states for stepping back and hold-to-rewind.
+///
+/// States are grouped in segments that start with a full keyframe, followed by
+/// one delta per step. A delta holds the XOR of the memory and framebuffer
+/// bytes that changed plus the small CPU state after the step, so it can be
+/// applied in either direction. Only the memory ranges the core marked dirty
+/// are compared, and the framebuffer only when it was drawn to. When the
+/// history grows past its capacity the oldest segment is dropped as a whole.
+#[derive(Debug, Clone)]
+pub struct Rewind {
+    segments: VecDeque<Segment>,
+    /// Full copy of the most recently pushed state, kept up to date by the deltas
+    last: Option<Chip8>,
+    len: usize,
+    capacity: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Segment {
+    keyframe: Chip8,
+    /// `deltas[i]` leads from state `i` of the segment to state `i + 1`, state 0 is the keyframe
+    deltas: Vec<Delta>,
+}
+
+#[derive(Debug, Clone)]
+struct Delta {
+    mem: Vec<(u16, u8)>,
+    gfx: Vec<(u8, u8, u8)>,
+    /// CPU state after the step
+    cpu: CpuState,
+}
+
+/// Everything in `Chip8` except memory and the framebuffer grid.
+///
+/// Platform, quirks and seed never change while a ROM runs and are left out.
+#[derive(Debug, Clone)]
+struct CpuState {
+    opcode: u16,
+    program_counter: u16,
+    vreg: [u8; 16],
+    ireg: u16,
+    hires: bool,
+    planes: u8,
+    delay_timer: u8,
+    sound_timer: u8,
+    stack: [u16; 16],
+    stack_pointer: u16,
+    keys: [u8; 16],
+    rpl: [u8; 16],
+    audio_pattern: [u8; 16],
+    pitch: u8,
+    exited: bool,
+    rng: ChaCha8Rng,
+    vblank: bool,
+}
+
+impl CpuState {
+    fn capture(chip8: &Chip8) -> Self {
+        CpuState {
+            opcode: chip8.opcode,
+            program_counter: chip8.program_counter,
+            vreg: chip8.vreg,
+            ireg: chip8.ireg,
+            hires: chip8.gfx.hires,
+            planes: chip8.gfx.planes,
+            delay_timer: chip8.delay_timer,
+            sound_timer: chip8.sound_timer,
+            stack: chip8.stack,
+            stack_pointer: chip8.stack_pointer,
+            keys: chip8.keys,
+            rpl: chip8.rpl,
+            audio_pattern: chip8.audio_pattern,
+            pitch: chip8.pitch,
+            exited: chip8.exited,
+            rng: chip8.rng.clone(),
+            vblank: chip8.vblank,
+        }
+    }
+
+    fn restore(&self, chip8: &mut Chip8) {
+        chip8.opcode = self.opcode;
+        chip8.program_counter = self.program_counter;
+        chip8.vreg = self.vreg;
+        chip8.ireg = self.ireg;
+        chip8.gfx.hires = self.hires;
+        chip8.gfx.planes = self.planes;
+        chip8.delay_timer = self.delay_timer;
+        chip8.sound_timer = self.sound_timer;
+        chip8.stack = self.stack;
+        chip8.stack_pointer = self.stack_pointer;
+        chip8.keys = self.keys;
+        chip8.rpl = self.rpl;
+        chip8.audio_pattern = self.audio_pattern;
+        chip8.pitch = self.pitch;
+        chip8.exited = self.exited;
+        chip8.rng.clone_from(&self.rng);
+        chip8.vblank = self.vblank;
+    }
+}
+
+impl Delta {
+    /// The changes from `last` to `to`, where only what `dirty` covers may
+    /// differ. `last` is brought up to date with `to` on the way.
+    fn record(last: &mut Chip8, to: &Chip8, dirty: &Dirty) -> Self {
+        let mut mem = Vec::new();
+        match &dirty.mem {
+            Some(ranges) => {
+                for range in ranges {
+                    let range = range.start.min(to.mem.len())..range.end.min(to.mem.len());
+                    diff_bytes(
+                        &mut last.mem[range.clone()],
+                        &to.mem[range.clone()],
+                        range.start,
+                        &mut mem,
+                    );
+                }
+            }
+            None => {
+                for (block, (a, b)) in last
+                    .mem
+                    .chunks_mut(BLOCK)
+                    .zip(to.mem.chunks(BLOCK))
+                    .enumerate()
+                {
+                    if a != b {
+                        diff_bytes(a, b, block * BLOCK, &mut mem);
+                    }
+                }
+            }
+        }
+        let mut gfx = Vec::new();
+        if dirty.gfx {
+            for (x, (a, b)) in last.gfx.grid.iter_mut().zip(to.gfx.grid.iter()).enumerate() {
+                if a == b {
+                    continue;
+                }
+                for (y, (p, q)) in a.iter_mut().zip(b).enumerate() {
+                    if p != q {
+                        gfx.push((x as u8, y as u8, *p ^ q));
+                        *p = *q;
+                    }
+                }
+            }
+        }
+        let cpu = CpuState::capture(to);
+        cpu.restore(last);
+        Delta { mem, gfx, cpu }
+    }
+
+    /// Applies the memory and framebuffer changes, forwards and backwards are the same
+    fn apply_diff(&self, chip8: &mut Chip8) {
+        for &(addr, xor) in &self.mem {
+            chip8.mem[addr as usize] ^= xor;
+        }
+        for &(x, y, xor) in &self.gfx {
+            chip8.gfx.grid[x as usize][y as usize] ^= xor;
+        }
+    }
+}
+
+/// Pushes the XOR of each byte of `to` that differs from `from`, numbered
+/// from `base`, and copies it over
+fn diff_bytes(from: &mut [u8], to: &[u8], base: usize, out: &mut Vec<(u16, u8)>) {
+    for (offset, (x, y)) in from.iter_mut().zip(to).enumerate() {
+        if x != y {
+            out.push(((base + offset) as u16, *x ^ y));
+            *x = *y;
+        }
+    }
+}
+
+impl Segment {
+    fn len(&self) -> usize {
+        self.deltas.len() + 1
+    }
+
+    /// Rebuilds the last state of the segment from the keyframe
+    fn last_state(&self) -> Chip8 {
+        let mut state = self.keyframe.clone();
+        for delta in &self.deltas {
+            delta.apply_diff(&mut state);
+        }
+        if let Some(delta) = self.deltas.last() {
+            delta.cpu.restore(&mut state);
+        }
+        state
+    }
+}
+
+impl Rewind {
+    /// A history holding at most `capacity` states
+    pub fn new(capacity: usize) -> Self {
+        Rewind {
+            segments: VecDeque::new(),
+            last: None,
+            len: 0,
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Number of states that can be stepped back through
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn clear(&mut self) {
+        self.segments.clear();
+        self.last = None;
+        self.len = 0;
+    }
+
+    /// Records `state`, dropping the oldest segment once over capacity
+    pub fn push(&mut self, state: &mut Chip8) {
+        let dirty = state.take_dirty();
+        match (self.segments.back_mut(), self.last.as_mut()) {
+            (Some(segment), Some(last))
+                if segment.len() < KEYFRAME_INTERVAL && last.mem.len() == state.mem.len() =>
+            {
+                segment.deltas.push(Delta::record(last, state, &dirty));
+            }
+            _ => {
+                self.segments.push_back(Segment {
+                    keyframe: state.clone(),
+                    deltas: Vec::new(),
+                });
+                self.last = Some(state.clone());
+            }
+        }
+        self.len += 1;
+        while self.segments.len() > 1 && self.len - self.segments[0].len() >= self.capacity {
+            let dropped = self.segments.pop_front().unwrap();
+            self.len -= dropped.len();
+        }
+    }
+
+    /// Takes the most recently pushed state back out
+    pub fn pop(&mut self) -> Option<Chip8> {
+        let mut state = self.last.take()?;
+        // It is compared against an older state on the next push
+        state.mark_all_dirty();
+        self.len -= 1;
+        let segment = self.segments.back_mut().unwrap();
+        match segment.deltas.pop() {
+            Some(delta) => {
+                let previous = match segment.deltas.last() {
+                    Some(before) => {
+                        let mut previous = state.clone();
+                        delta.apply_diff(&mut previous);
+                        before.cpu.restore(&mut previous);
+                        previous
+                    }
+                    None => segment.keyframe.clone(),
+                };
+                self.last = Some(previous);
+            }
+            None => {
+                self.segments.pop_back();
+                self.last = self.segments.back().map(Segment::last_state);
+            }
+        }
+        Some(state)
+    }
+}
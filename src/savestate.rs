@@ -0,0 +1,223 @@
+//! Versioned binary save states.
+//!
+//! Layout, all integers little endian:
+//!
+//! ```text
+//! magic "C8ST" | version u16 | ROM hash u64 | machine state
+//! ```
+//!
+//! A state is only loaded into the ROM it was saved from and by the version
+//! that wrote it.
+
+use std::{
+    error, fmt,
+    io::{self, Read, Write},
+};
+
+use crate::core::Chip8;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+
+pub const MAGIC: &[u8; 4] = b"C8ST";
+pub const VERSION: u16 = 1;
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    Io(io::Error),
+    NotASaveState,
+    UnsupportedVersion(u16),
+    RomMismatch { expected: u64, found: u64 },
+    Truncated,
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::Io(err) => write!(f, "{}", err),
+            SaveStateError::NotASaveState => write!(f, "not a chipterm save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} is not supported, expected version {}",
+                version, VERSION
+            ),
+            SaveStateError::RomMismatch { expected, found } => write!(
+                f,
+                "save state belongs to another ROM (hash {:016x}, this ROM is {:016x})",
+                found, expected
+            ),
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+            SaveStateError::Corrupt(what) => write!(f, "corrupt save state: bad {}", what),
+        }
+    }
+}
+
+impl error::Error for SaveStateError {}
+
+impl From<io::Error> for SaveStateError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => SaveStateError::Truncated,
+            _ => SaveStateError::Io(err),
+        }
+    }
+}
+
+/// Identifies a ROM image
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    fnv1a(rom)
+}
+
+/// Hash of the serialized machine, equal states hash the same
+pub fn state_hash(chip8: &Chip8) -> u64 {
+    let mut state = Vec::new();
+    write_state(chip8, &mut state).expect("writing to a Vec can't fail");
+    fnv1a(&state)
+}
+
+/// 64-bit FNV-1a
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn save(chip8: &Chip8, rom_hash: u64, out: &mut impl Write) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&rom_hash.to_le_bytes())?;
+    write_state(chip8, out)
+}
+
+pub fn load(input: &mut impl Read, rom_hash: u64) -> Result<Chip8, SaveStateError> {
+    let mut magic = [0; 4];
+    input
+        .read_exact(&mut magic)
+        .map_err(|_| SaveStateError::NotASaveState)?;
+    if &magic != MAGIC {
+        return Err(SaveStateError::NotASaveState);
+    }
+    let version = read_u16(input)?;
+    if version != VERSION {
+        return Err(SaveStateError::UnsupportedVersion(version));
+    }
+    let found = read_u64(input)?;
+    if found != rom_hash {
+        return Err(SaveStateError::RomMismatch {
+            expected: rom_hash,
+            found,
+        });
+    }
+    read_state(input)
+}
+
+/// Serializes the machine state alone, without the header
+pub fn write_state(chip8: &Chip8, out: &mut impl Write) -> io::Result<()> {
+    let platform = match chip8.platform {
+        Platform::Chip8 => 0u8,
+        Platform::SuperChip => 1,
+        Platform::XoChip => 2,
+    };
+    out.write_all(&[platform])?;
+    write_quirks(&chip8.quirks, out)?;
+    out.write_all(&chip8.seed.to_le_bytes())?;
+    out.write_all(&chip8.opcode.to_le_bytes())?;
+    out.write_all(&chip8.program_counter.to_le_bytes())?;
+    out.write_all(&(chip8.mem.len() as u32).to_le_bytes())?;
+    out.write_all(&chip8.mem)?;
+    out.write_all(&chip8.vreg)?;
+    out.write_all(&chip8.ireg.to_le_bytes())?;
+    out.write_all(&[chip8.gfx.hires as u8, chip8.gfx.planes])?;
+    for column in chip8.gfx.grid.iter() {
+        out.write_all(column)?;
+    }
+    out.write_all(&[chip8.delay_timer, chip8.sound_timer])?;
+    for addr in chip8.stack.iter() {
+        out.write_all(&addr.to_le_bytes())?;
+    }
+    out.write_all(&chip8.stack_pointer.to_le_bytes())?;
+    out.write_all(&chip8.keys)?;
+    out.write_all(&chip8.rpl)?;
+    out.write_all(&chip8.audio_pattern)?;
+    out.write_all(&[chip8.pitch, chip8.exited as u8, chip8.vblank as u8])?;
+    out.write_all(&chip8.rng.get_word_pos().to_le_bytes())?;
+    Ok(())
+}
+
+fn read_state(input: &mut impl Read) -> Result<Chip8, SaveStateError> {
+    let platform = match read_u8(input)? {
+        0 => Platform::Chip8,
+        1 => Platform::SuperChip,
+        2 => Platform::XoChip,
+        _ => return Err(SaveStateError::Corrupt("platform")),
+    };
+    let quirks = read_quirks(input)?;
+    let seed = read_u64(input)?;
+    let mut chip8 = Chip8::new(platform, quirks, seed);
+    chip8.opcode = read_u16(input)?;
+    chip8.program_counter = read_u16(input)?;
+    if read_u32(input)? as usize != platform.mem_size() {
+        return Err(SaveStateError::Corrupt("memory size"));
+    }
+    input.read_exact(&mut chip8.mem)?;
+    input.read_exact(&mut chip8.vreg)?;
+    chip8.ireg = read_u16(input)?;
+    chip8.gfx.hires = read_u8(input)? != 0;
+    chip8.gfx.planes = read_u8(input)?;
+    for column in chip8.gfx.grid.iter_mut() {
+        input.read_exact(column)?;
+    }
+    chip8.delay_timer = read_u8(input)?;
+    chip8.sound_timer = read_u8(input)?;
+    for addr in chip8.stack.iter_mut() {
+        *addr = read_u16(input)?;
+    }
+    chip8.stack_pointer = read_u16(input)?;
+    if chip8.stack_pointer as usize > chip8.stack.len() {
+        return Err(SaveStateError::Corrupt("stack pointer"));
+    }
+    input.read_exact(&mut chip8.keys)?;
+    input.read_exact(&mut chip8.rpl)?;
+    input.read_exact(&mut chip8.audio_pattern)?;
+    chip8.pitch = read_u8(input)?;
+    chip8.exited = read_u8(input)? != 0;
+    chip8.vblank = read_u8(input)? != 0;
+    let mut word_pos = [0; 16];
+    input.read_exact(&mut word_pos)?;
+    chip8.rng.set_word_pos(u128::from_le_bytes(word_pos));
+    Ok(chip8)
+}
+
+fn write_quirks(quirks: &Quirks, out: &mut impl Write) -> io::Result<()> {
+    out.write_all(&quirks.values())
+}
+
+fn read_quirks(input: &mut impl Read) -> Result<Quirks, SaveStateError> {
+    let mut values = [0; 6];
+    input.read_exact(&mut values)?;
+    Quirks::from_values(values).map_err(|_| SaveStateError::Corrupt("quirks"))
+}
+
+fn read_u8(input: &mut impl Read) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u16(input: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    input.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
@@ -0,0 +1,52 @@
+use std::time::{Duration, Instant};
+
+/// CHIP-8 timers and the display refresh run at 60Hz.
+pub const FRAME_RATE: u32 = 60;
+
+/// Paces emulation in 60Hz frames.
+///
+/// Each frame the frontend runs `cycles_per_frame` instructions and then calls
+/// `Chip8::end_frame`, so emulation speed doesn't depend on how precisely the
+/// OS wakes threads up.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    pub cycles_per_frame: u32,
+    /// Frames run so far
+    pub frame: u64,
+    frame_duration: Duration,
+    next_frame: Instant,
+}
+
+impl Scheduler {
+    /// A scheduler whose first frame is due right away
+    pub fn new(cycles_per_frame: u32) -> Self {
+        Scheduler {
+            cycles_per_frame,
+            frame: 0,
+            frame_duration: Duration::from_secs(1) / FRAME_RATE,
+            next_frame: Instant::now(),
+        }
+    }
+
+    /// Time left until the next frame is due
+    pub fn timeout(&self) -> Duration {
+        self.next_frame.saturating_duration_since(Instant::now())
+    }
+
+    /// Returns true and schedules the following frame when a frame is due.
+    ///
+    /// Frames missed by more than a few deadlines (a suspended terminal, a slow
+    /// redraw) are dropped instead of being run back to back.
+    pub fn frame_due(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next_frame {
+            return false;
+        }
+        self.frame += 1;
+        self.next_frame += self.frame_duration;
+        if now > self.next_frame + self.frame_duration * 4 {
+            self.next_frame = now + self.frame_duration;
+        }
+        true
+    }
+}
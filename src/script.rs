@@ -0,0 +1,335 @@
+//! Rhai scripts driving the emulator, loaded with `--script`.
+//!
+//! A script defines any of these hooks, each getting the machine as `chip8`:
+//!
+//! ```text
+//! fn on_frame(chip8, frame) { }         // after each 60Hz frame, paused or not
+//! fn on_instruction(chip8, pc, opcode) { }  // before each instruction runs
+//! fn on_break(chip8, reason) { }        // when a breakpoint, watchpoint or error pauses
+//! ```
+//!
+//! `chip8` has `v(x)`/`set_v(x, value)`, the `i`, `pc`, `sp`, `dt` and `st`
+//! properties, `peek(addr)`/`poke(addr, value)`, `press_key(key)`/
+//! `release_key(key)`, `pixel(x, y)`, `screenshot()` returning the display as
+//! text or `screenshot(path)` writing it to a file, and `pause()`/`resume()`
+//! with a `paused` property. `print` output goes to the caller.
+
+use std::{cell::RefCell, convert::TryFrom, error, fmt, fs, path::Path, rc::Rc};
+
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Scope, AST, INT};
+
+use crate::core::Chip8;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError(String);
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for ScriptError {}
+
+impl From<Box<EvalAltResult>> for ScriptError {
+    fn from(err: Box<EvalAltResult>) -> Self {
+        ScriptError(err.to_string())
+    }
+}
+
+/// An event scripts can react to
+#[derive(Debug, Clone, Copy)]
+pub enum Hook<'a> {
+    Frame(u64),
+    Instruction { pc: u16, opcode: u16 },
+    Break(&'a str),
+}
+
+impl Hook<'_> {
+    fn name(&self) -> &'static str {
+        match self {
+            Hook::Frame(_) => "on_frame",
+            Hook::Instruction { .. } => "on_instruction",
+            Hook::Break(_) => "on_break",
+        }
+    }
+}
+
+/// What the `chip8` handle reaches while a hook runs
+#[derive(Debug)]
+struct Machine {
+    chip8: Chip8,
+    paused: bool,
+}
+
+/// The `chip8` argument of the hooks, the machine is swapped in for each call
+#[derive(Debug, Clone)]
+struct Handle(Rc<RefCell<Machine>>);
+
+type Result<T> = std::result::Result<T, Box<EvalAltResult>>;
+
+impl Handle {
+    fn with<T>(&mut self, f: impl FnOnce(&mut Machine) -> T) -> T {
+        f(&mut self.0.borrow_mut())
+    }
+}
+
+#[derive(Debug)]
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    handle: Handle,
+    /// Names of the hooks the script defines
+    hooks: Vec<&'static str>,
+    /// Lines printed since the last `take_output`
+    output: Rc<RefCell<Vec<String>>>,
+}
+
+impl Script {
+    /// Compiles the script at `path` and runs its top level statements
+    pub fn load(path: &Path) -> std::result::Result<Script, ScriptError> {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut engine = Engine::new();
+        let printed = output.clone();
+        engine.on_print(move |s| printed.borrow_mut().push(s.to_owned()));
+        let debugged = output.clone();
+        engine.on_debug(move |s, _, pos| debugged.borrow_mut().push(format!("{}: {}", pos, s)));
+        register(&mut engine);
+
+        let ast = engine.compile_file(path.to_path_buf())?;
+        let mut scope = Scope::new();
+        engine.run_ast_with_scope(&mut scope, &ast)?;
+        let hooks = [("on_frame", 2), ("on_instruction", 3), ("on_break", 2)]
+            .iter()
+            .filter(|&&(name, params)| {
+                ast.iter_functions()
+                    .any(|f| f.name == name && f.params.len() == params)
+            })
+            .map(|&(name, _)| name)
+            .collect();
+        let placeholder = Chip8::new(Platform::Chip8, Quirks::default(), 0);
+        Ok(Script {
+            engine,
+            ast,
+            scope,
+            handle: Handle(Rc::new(RefCell::new(Machine {
+                chip8: placeholder,
+                paused: false,
+            }))),
+            hooks,
+            output,
+        })
+    }
+
+    /// Whether the script defines a function for `hook`
+    pub fn handles(&self, hook: Hook) -> bool {
+        self.hooks.contains(&hook.name())
+    }
+
+    /// Runs the script's function for `hook`, if it has one. `paused` is
+    /// what the script sees and sets with `pause()` and `resume()`.
+    pub fn call(
+        &mut self,
+        hook: Hook,
+        chip8: &mut Chip8,
+        paused: &mut bool,
+    ) -> std::result::Result<(), ScriptError> {
+        if !self.handles(hook) {
+            return Ok(());
+        }
+        self.handle.with(|machine| {
+            std::mem::swap(&mut machine.chip8, chip8);
+            machine.paused = *paused;
+        });
+        let mut options = CallFnOptions::new();
+        options.eval_ast = false;
+        let handle = self.handle.clone();
+        let result = match hook {
+            Hook::Frame(frame) => self.engine.call_fn_with_options::<Dynamic>(
+                options,
+                &mut self.scope,
+                &self.ast,
+                hook.name(),
+                (handle, frame as INT),
+            ),
+            Hook::Instruction { pc, opcode } => self.engine.call_fn_with_options::<Dynamic>(
+                options,
+                &mut self.scope,
+                &self.ast,
+                hook.name(),
+                (handle, pc as INT, opcode as INT),
+            ),
+            Hook::Break(reason) => self.engine.call_fn_with_options::<Dynamic>(
+                options,
+                &mut self.scope,
+                &self.ast,
+                hook.name(),
+                (handle, reason.to_owned()),
+            ),
+        };
+        self.handle.with(|machine| {
+            std::mem::swap(&mut machine.chip8, chip8);
+            *paused = machine.paused;
+        });
+        result.map(|_| ()).map_err(ScriptError::from)
+    }
+
+    /// Lines the script printed since the last call
+    pub fn take_output(&mut self) -> Vec<String> {
+        self.output.borrow_mut().drain(..).collect()
+    }
+}
+
+fn register(engine: &mut Engine) {
+    engine.register_type_with_name::<Handle>("Chip8");
+
+    engine.register_fn("v", |h: &mut Handle, x: INT| -> Result<INT> {
+        let x = register_index(x)?;
+        Ok(h.with(|m| m.chip8.vreg[x] as INT))
+    });
+    engine.register_fn(
+        "set_v",
+        |h: &mut Handle, x: INT, value: INT| -> Result<()> {
+            let x = register_index(x)?;
+            let value = byte(value)?;
+            h.with(|m| m.chip8.vreg[x] = value);
+            Ok(())
+        },
+    );
+    engine.register_get_set(
+        "i",
+        |h: &mut Handle| h.with(|m| m.chip8.ireg as INT),
+        |h: &mut Handle, value: INT| -> Result<()> {
+            let value = word(value)?;
+            h.with(|m| m.chip8.ireg = value);
+            Ok(())
+        },
+    );
+    engine.register_get_set(
+        "pc",
+        |h: &mut Handle| h.with(|m| m.chip8.program_counter as INT),
+        |h: &mut Handle, value: INT| -> Result<()> {
+            let value = word(value)?;
+            h.with(|m| m.chip8.program_counter = value);
+            Ok(())
+        },
+    );
+    engine.register_get("sp", |h: &mut Handle| {
+        h.with(|m| m.chip8.stack_pointer as INT)
+    });
+    engine.register_get_set(
+        "dt",
+        |h: &mut Handle| h.with(|m| m.chip8.delay_timer as INT),
+        |h: &mut Handle, value: INT| -> Result<()> {
+            let value = byte(value)?;
+            h.with(|m| m.chip8.delay_timer = value);
+            Ok(())
+        },
+    );
+    engine.register_get_set(
+        "st",
+        |h: &mut Handle| h.with(|m| m.chip8.sound_timer as INT),
+        |h: &mut Handle, value: INT| -> Result<()> {
+            let value = byte(value)?;
+            h.with(|m| m.chip8.sound_timer = value);
+            Ok(())
+        },
+    );
+
+    engine.register_fn("peek", |h: &mut Handle, addr: INT| -> Result<INT> {
+        h.with(|m| {
+            let addr = address(&m.chip8, addr)?;
+            Ok(m.chip8.mem[addr] as INT)
+        })
+    });
+    engine.register_fn(
+        "poke",
+        |h: &mut Handle, addr: INT, value: INT| -> Result<()> {
+            let value = byte(value)?;
+            h.with(|m| {
+                let addr = address(&m.chip8, addr)?;
+                m.chip8.mem[addr] = value;
+                m.chip8.mark_dirty(addr..addr + 1);
+                Ok(())
+            })
+        },
+    );
+
+    engine.register_fn("press_key", |h: &mut Handle, key: INT| -> Result<()> {
+        let key = key_index(key)?;
+        h.with(|m| m.chip8.press_key(key));
+        Ok(())
+    });
+    engine.register_fn("release_key", |h: &mut Handle, key: INT| -> Result<()> {
+        let key = key_index(key)?;
+        h.with(|m| m.chip8.release_key(key));
+        Ok(())
+    });
+
+    engine.register_fn("pixel", |h: &mut Handle, x: INT, y: INT| -> Result<INT> {
+        h.with(|m| {
+            let gfx = &m.chip8.gfx;
+            if !(0..gfx.width() as INT).contains(&x) || !(0..gfx.height() as INT).contains(&y) {
+                return Err(format!("pixel ({}, {}) is off the screen", x, y).into());
+            }
+            Ok(gfx.pixel(x as usize, y as usize) as INT)
+        })
+    });
+    engine.register_fn("screenshot", |h: &mut Handle| {
+        h.with(|m| screenshot(&m.chip8))
+    });
+    engine.register_fn("screenshot", |h: &mut Handle, path: &str| -> Result<()> {
+        let text = h.with(|m| screenshot(&m.chip8));
+        fs::write(path, text).map_err(|err| format!("{}: {}", path, err).into())
+    });
+
+    engine.register_fn("pause", |h: &mut Handle| h.with(|m| m.paused = true));
+    engine.register_fn("resume", |h: &mut Handle| h.with(|m| m.paused = false));
+    engine.register_get("paused", |h: &mut Handle| h.with(|m| m.paused));
+}
+
+/// The display as lines of `.` for off and `#`, `+` or `@` for pixels lit on
+/// the first, second or both XO-CHIP planes
+fn screenshot(chip8: &Chip8) -> String {
+    let gfx = &chip8.gfx;
+    let mut text = String::with_capacity((gfx.width() + 1) * gfx.height());
+    for y in 0..gfx.height() {
+        for x in 0..gfx.width() {
+            text.push(['.', '#', '+', '@'][(gfx.pixel(x, y) & 3) as usize]);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn register_index(x: INT) -> Result<usize> {
+    match x {
+        0..=15 => Ok(x as usize),
+        _ => Err(format!("no register V{}", x).into()),
+    }
+}
+
+fn key_index(key: INT) -> Result<u8> {
+    match key {
+        0..=15 => Ok(key as u8),
+        _ => Err(format!("no key {}", key).into()),
+    }
+}
+
+fn byte(value: INT) -> Result<u8> {
+    u8::try_from(value).map_err(|_| format!("{} does not fit in a byte", value).into())
+}
+
+fn word(value: INT) -> Result<u16> {
+    u16::try_from(value).map_err(|_| format!("{} does not fit in 16 bits", value).into())
+}
+
+fn address(chip8: &Chip8, addr: INT) -> Result<usize> {
+    match usize::try_from(addr) {
+        Ok(addr) if addr < chip8.mem.len() => Ok(addr),
+        _ => Err(format!("address {:#X} is outside memory", addr).into()),
+    }
+}
@@ -0,0 +1,220 @@
+//! Symbol files naming addresses in a ROM, as written by `chipterm asm --symbols`,
+//! and source maps giving the source line of each instruction, as written by
+//! `chipterm asm --source-map`.
+//!
+//! ```text
+//! ; address name
+//! 0200 start
+//! 020E sub_20E
+//!
+//! ; address line file
+//! 0200 12 /home/me/pong.asm
+//! ```
+//!
+//! Addresses are hex, blank lines and `;` comments are ignored.
+
+use std::{
+    collections::BTreeMap,
+    error, fmt, fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::debugger::parse_address;
+
+#[derive(Debug)]
+pub enum SymbolError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolError::Io(err) => write!(f, "{}", err),
+            SymbolError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl error::Error for SymbolError {}
+
+impl From<io::Error> for SymbolError {
+    fn from(err: io::Error) -> Self {
+        SymbolError::Io(err)
+    }
+}
+
+/// Names by address, the first name given to an address wins.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    names: BTreeMap<u16, String>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols::default()
+    }
+
+    pub fn insert(&mut self, addr: u16, name: impl Into<String>) {
+        self.names.entry(addr).or_insert_with(|| name.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// The closest symbol at or below `addr` and how far past it `addr` is
+    pub fn lookup(&self, addr: u16) -> Option<(&str, u16)> {
+        self.names
+            .range(..=addr)
+            .next_back()
+            .map(|(&start, name)| (name.as_str(), addr - start))
+    }
+
+    /// The address named `name`
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.names
+            .iter()
+            .find(|(_, n)| n.as_str() == name)
+            .map(|(&addr, _)| addr)
+    }
+
+    /// `addr` as `name` or `name+0x4`, or in hex below the first symbol
+    pub fn describe(&self, addr: u16) -> String {
+        match self.lookup(addr) {
+            Some((name, 0)) => name.to_owned(),
+            Some((name, offset)) => format!("{}+{:#X}", name, offset),
+            None => format!("{:#05X}", addr),
+        }
+    }
+
+    pub fn read(input: impl BufRead) -> Result<Symbols, SymbolError> {
+        let mut symbols = Symbols::new();
+        for (n, line) in input.lines().enumerate() {
+            let line = line?;
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some(addr), Some(name), None) => {
+                    let addr = parse_address(addr).map_err(|message| SymbolError::Parse {
+                        line: n + 1,
+                        message,
+                    })?;
+                    symbols.insert(addr, name);
+                }
+                _ => {
+                    return Err(SymbolError::Parse {
+                        line: n + 1,
+                        message: format!("expected '<address> <name>', found '{}'", line),
+                    })
+                }
+            }
+        }
+        Ok(symbols)
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        for (addr, name) in &self.names {
+            writeln!(out, "{:04X} {}", addr, name)?;
+        }
+        Ok(())
+    }
+}
+
+/// The file and line each instruction was assembled from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    files: Vec<PathBuf>,
+    /// Index in `files` and 1-based line by address
+    lines: BTreeMap<u16, (usize, usize)>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap::default()
+    }
+
+    pub fn insert(&mut self, addr: u16, file: &Path, line: usize) {
+        let file = match self.files.iter().position(|f| f == file) {
+            Some(index) => index,
+            None => {
+                self.files.push(file.to_path_buf());
+                self.files.len() - 1
+            }
+        };
+        self.lines.insert(addr, (file, line));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Where the instruction at `addr` came from
+    pub fn location(&self, addr: u16) -> Option<(&Path, usize)> {
+        self.lines
+            .get(&addr)
+            .map(|&(file, line)| (self.files[file].as_path(), line))
+    }
+
+    /// The first instruction on `line` of `file`, or on the closest line after
+    /// it, and the line it is on
+    pub fn address(&self, file: &Path, line: usize) -> Option<(u16, usize)> {
+        let index = self.files.iter().position(|f| f == file)?;
+        self.lines
+            .iter()
+            .filter(|&(_, &(f, l))| f == index && l >= line)
+            .min_by_key(|&(&addr, &(_, l))| (l, addr))
+            .map(|(&addr, &(_, l))| (addr, l))
+    }
+
+    /// Makes the file paths absolute, relative ones are taken from `dir`
+    pub fn resolve_paths(&mut self, dir: &Path) {
+        for file in &mut self.files {
+            let path = dir.join(&file);
+            *file = fs::canonicalize(&path).unwrap_or(path);
+        }
+    }
+
+    pub fn read(input: impl BufRead) -> Result<SourceMap, SymbolError> {
+        let mut map = SourceMap::new();
+        for (n, line) in input.lines().enumerate() {
+            let line = line?;
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| SymbolError::Parse {
+                line: n + 1,
+                message,
+            };
+            let mut words = line.splitn(3, char::is_whitespace);
+            match (words.next(), words.next(), words.next()) {
+                (Some(addr), Some(source_line), Some(file)) if !file.trim().is_empty() => {
+                    let addr = parse_address(addr).map_err(error)?;
+                    let source_line = source_line
+                        .parse()
+                        .map_err(|_| error(format!("invalid line number '{}'", source_line)))?;
+                    map.insert(addr, Path::new(file.trim()), source_line);
+                }
+                _ => {
+                    return Err(error(format!(
+                        "expected '<address> <line> <file>', found '{}'",
+                        line
+                    )))
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        for (addr, &(file, line)) in &self.lines {
+            writeln!(out, "{:04X} {} {}", addr, line, self.files[file].display())?;
+        }
+        Ok(())
+    }
+}
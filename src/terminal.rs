@@ -0,0 +1,24 @@
+//! tui frontend glue for the emulator core.
+
+use tui::{style::Color, widgets::canvas::Shape};
+
+use crate::display::Display;
+
+impl Shape for Display {
+    fn draw(&self, painter: &mut tui::widgets::canvas::Painter) {
+        let max_y = self.height();
+        let max_x = self.width();
+        for y in 0..max_y {
+            for x in 0..max_x {
+                let color = match self.grid[x][y] {
+                    0 => continue,
+                    1 => Color::Reset,
+                    2 => Color::LightRed,
+                    _ => Color::Yellow,
+                };
+                let (x, y) = painter.get_point(x as f64, (max_y - 1 - y) as f64).unwrap();
+                painter.paint(x, y, color)
+            }
+        }
+    }
+}
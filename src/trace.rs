@@ -0,0 +1,99 @@
+//! Instruction traces, one line per executed instruction.
+//!
+//! Each line shows the machine right before the instruction runs:
+//!
+//! ```text
+//! 0000000042 0204 8374 V:1200000000000000F000000000000000 I:0300 SP:00 DT:00 ST:00 ADD V3, V7
+//! ```
+//!
+//! That is the cycle number in decimal counted from 0, then PC, opcode, V0 to
+//! VF, I, stack pointer, delay and sound timers in hex, then the mnemonic.
+//! The columns never move, so traces diff line by line.
+
+use std::{
+    io::{self, Write},
+    ops::RangeInclusive,
+};
+
+use crate::core::Chip8;
+use crate::instruction::Instruction;
+
+/// Writes a trace of the instructions run through `record`.
+#[derive(Debug)]
+pub struct Tracer<W: Write> {
+    out: W,
+    /// Instructions recorded so far, traced or not
+    cycle: u64,
+    /// Only instructions at these addresses are written
+    range: Option<RangeInclusive<u16>>,
+    /// Nothing is written until this is set
+    armed: bool,
+    /// The first write error, tracing stops there
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+    /// A tracer writing every instruction in `range`, or all of them without one
+    pub fn new(out: W, range: Option<RangeInclusive<u16>>) -> Self {
+        Tracer {
+            out,
+            cycle: 0,
+            range,
+            armed: true,
+            error: None,
+        }
+    }
+
+    /// Holds the trace back until `arm` is called
+    pub fn disarmed(mut self) -> Self {
+        self.armed = false;
+        self
+    }
+
+    pub fn arm(&mut self) {
+        self.armed = true;
+    }
+
+    /// Logs the instruction `chip8` is about to execute. Write errors are
+    /// kept for `finish`, so tracing never interrupts the emulator.
+    pub fn record(&mut self, chip8: &Chip8) {
+        let cycle = self.cycle;
+        self.cycle += 1;
+        let pc = chip8.program_counter;
+        if !self.armed
+            || self.error.is_some()
+            || self.range.as_ref().is_some_and(|r| !r.contains(&pc))
+        {
+            return;
+        }
+        let opcode = match (chip8.mem.get(pc as usize), chip8.mem.get(pc as usize + 1)) {
+            (Some(&hi), Some(&lo)) => (hi as u16) << 8 | lo as u16,
+            _ => 0,
+        };
+        let mnemonic =
+            Instruction::decode(opcode).map_or_else(|| String::from("???"), |i| i.to_string());
+        let vreg: String = chip8.vreg.iter().map(|v| format!("{:02X}", v)).collect();
+        let written = writeln!(
+            self.out,
+            "{:010} {:04X} {:04X} V:{} I:{:04X} SP:{:02X} DT:{:02X} ST:{:02X} {}",
+            cycle,
+            pc,
+            opcode,
+            vreg,
+            chip8.ireg,
+            chip8.stack_pointer,
+            chip8.delay_timer,
+            chip8.sound_timer,
+            mnemonic
+        );
+        self.error = written.err();
+    }
+
+    /// Flushes the trace, or returns the error that stopped it
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.out.flush(),
+        }
+    }
+}
@@ -1,4 +1,8 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+};
 
 use lazy_static::lazy_static;
 
@@ -24,3 +28,19 @@ lazy_static! {
         map
     };
 }
+
+/// Per-ROM directory for save states, under `$XDG_DATA_HOME` or `~/.local/share`
+pub fn state_dir(rompath: &Path, rom_hash: u64) -> PathBuf {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    let rom_name = rompath
+        .file_stem()
+        .map_or_else(|| "rom".into(), |stem| stem.to_string_lossy());
+    data_home
+        .join("chipterm")
+        .join("states")
+        .join(format!("{}-{:016x}", rom_name, rom_hash))
+}